@@ -0,0 +1,171 @@
+use raylib::prelude::*;
+
+use crate::canvas::Canvas;
+use crate::gamemap::{GameMap, PickupKind};
+use crate::input::{Action, InputFrame};
+use crate::player::Player;
+
+const DEFAULT_ZOOM: f32 = 16.0; // Pixels per tile
+const MIN_ZOOM: f32 = 4.0;
+const MAX_ZOOM: f32 = 64.0;
+const ZOOM_SPEED: f32 = 1.03; // Zoom factor applied per frame while a zoom key is held
+const PAN_SPEED: f32 = 4.0; // Pixels per frame, so panning feels the same at every zoom level
+const FOV_RANGE: f64 = 8.0; // How far the view cone reaches when it does not hit a wall
+
+const BACKGROUND_COLOR: Color = Color::new(16, 16, 16, 255);
+const WALL_COLOR: Color = Color::new(200, 70, 60, 255);
+const DOOR_COLOR: Color = Color::new(170, 120, 60, 255);
+const OPEN_DOOR_COLOR: Color = Color::new(90, 70, 40, 255);
+const PLAYER_COLOR: Color = Color::new(255, 255, 255, 255);
+const FOV_COLOR: Color = Color::new(90, 90, 90, 255);
+
+// Full screen map of the level as far as the player has seen it. It is drawn in place of
+// the 3D view, with world y pointing up so that turning left on the map matches the view.
+pub struct Automap {
+    pub is_open: bool,
+    zoom: f32,
+    pan: Vector2, // Offset of the map centre from the player, in tiles
+}
+
+impl Default for Automap {
+    fn default() -> Self {
+        Automap {
+            is_open: false,
+            zoom: DEFAULT_ZOOM,
+            pan: Vector2::zero(),
+        }
+    }
+}
+
+impl Automap {
+    pub fn update(&mut self, input: &InputFrame) {
+        if input.is_pressed(Action::Automap) {
+            self.is_open = !self.is_open;
+        }
+        if !self.is_open {
+            return;
+        }
+
+        if input.is_down(Action::MapZoomIn) {
+            self.zoom = (self.zoom * ZOOM_SPEED).min(MAX_ZOOM);
+        }
+        if input.is_down(Action::MapZoomOut) {
+            self.zoom = (self.zoom / ZOOM_SPEED).max(MIN_ZOOM);
+        }
+
+        let step = PAN_SPEED / self.zoom;
+        if input.is_down(Action::MenuLeft) {
+            self.pan.x -= step;
+        }
+        if input.is_down(Action::MenuRight) {
+            self.pan.x += step;
+        }
+        if input.is_down(Action::MenuUp) {
+            self.pan.y += step;
+        }
+        if input.is_down(Action::MenuDown) {
+            self.pan.y -= step;
+        }
+        if input.is_pressed(Action::MapReset) {
+            self.pan = Vector2::zero();
+            self.zoom = DEFAULT_ZOOM;
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, map: &GameMap, player: &Player) {
+        canvas.fill_rect(0, 0, canvas.width, canvas.height, BACKGROUND_COLOR);
+
+        let centre = player.pos + self.pan;
+        let (half_width, half_height) = (canvas.width as f32 / 2.0, canvas.height as f32 / 2.0);
+        let to_screen = |x: f32, y: f32| -> (i32, i32) {
+            (
+                (half_width + (x - centre.x) * self.zoom).round() as i32,
+                (half_height - (y - centre.y) * self.zoom).round() as i32,
+            )
+        };
+        let line = |canvas: &mut Canvas, from: (f32, f32), to: (f32, f32), color: Color| {
+            let (x0, y0) = to_screen(from.0, from.1);
+            let (x1, y1) = to_screen(to.0, to.1);
+            canvas.draw_line(x0, y0, x1, y1, color);
+        };
+
+        // View cone, clipped against the walls the same way the renderer sees them
+        for side in [-1.0, 1.0] {
+            let dir = player.dir + player.projection * side;
+            let length = (dir.x * dir.x + dir.y * dir.y).sqrt();
+            let (dir_x, dir_y) = ((dir.x / length) as f64, (dir.y / length) as f64);
+            let dist = map.cast_ray(player.pos.x as f64, player.pos.y as f64, dir_x, dir_y, FOV_RANGE);
+            let end = (player.pos.x + (dir_x * dist) as f32, player.pos.y + (dir_y * dist) as f32);
+            line(canvas, (player.pos.x, player.pos.y), end, FOV_COLOR);
+        }
+
+        // Only the edges of known walls that face open space are drawn
+        for y in 0..map.size as i32 {
+            for x in 0..map.size as i32 {
+                if !map.is_explored(x, y) {
+                    continue;
+                }
+                let tile = map.map_data[y as usize * map.size + x as usize];
+                let (fx, fy) = (x as f32, y as f32);
+
+                if let Some(door) = map.door_at(x as usize, y as usize) {
+                    let color = match (door.is_open, door.key) {
+                        (true, _) => OPEN_DOOR_COLOR,
+                        (false, Some(key)) => key.color(),
+                        (false, None) => DOOR_COLOR,
+                    };
+                    // Doors are drawn across the middle of their cell, along the passage walls
+                    if map.is_wall(x - 1, y) && map.is_wall(x + 1, y) {
+                        line(canvas, (fx, fy + 0.5), (fx + 1.0, fy + 0.5), color);
+                    } else {
+                        line(canvas, (fx + 0.5, fy), (fx + 0.5, fy + 1.0), color);
+                    }
+                    continue;
+                }
+                if tile == 0 {
+                    continue;
+                }
+                if let Some((a, b)) = map.wall_segment(x, y) {
+                    line(canvas, (a.0 as f32, a.1 as f32), (b.0 as f32, b.1 as f32), WALL_COLOR);
+                    continue;
+                }
+
+                if !map.is_wall(x - 1, y) {
+                    line(canvas, (fx, fy), (fx, fy + 1.0), WALL_COLOR);
+                }
+                if !map.is_wall(x + 1, y) {
+                    line(canvas, (fx + 1.0, fy), (fx + 1.0, fy + 1.0), WALL_COLOR);
+                }
+                if !map.is_wall(x, y - 1) {
+                    line(canvas, (fx, fy), (fx + 1.0, fy), WALL_COLOR);
+                }
+                if !map.is_wall(x, y + 1) {
+                    line(canvas, (fx, fy + 1.0), (fx + 1.0, fy + 1.0), WALL_COLOR);
+                }
+            }
+        }
+
+        // Keys lying in cells the player has seen
+        let key_size = (self.zoom / 4.0).max(2.0) as i32;
+        for pickup in map.pickups.iter().filter(|pickup| !pickup.is_taken) {
+            let PickupKind::Key(key) = pickup.kind else {
+                continue;
+            };
+            if !map.is_explored(pickup.x as i32, pickup.y as i32) {
+                continue;
+            }
+            let (x, y) = to_screen(pickup.x as f32, pickup.y as f32);
+            canvas.fill_rect(x - key_size / 2, y - key_size / 2, key_size, key_size, key.color());
+        }
+
+        // Player arrow
+        let perp = Vector2::new(-player.dir.y, player.dir.x);
+        let tip = player.pos + player.dir * 0.4;
+        let tail = player.pos - player.dir * 0.3;
+        let left = tail + perp * 0.25;
+        let right = tail - perp * 0.25;
+        line(canvas, (tail.x, tail.y), (tip.x, tip.y), PLAYER_COLOR);
+        line(canvas, (left.x, left.y), (tip.x, tip.y), PLAYER_COLOR);
+        line(canvas, (right.x, right.y), (tip.x, tip.y), PLAYER_COLOR);
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::Instant;
+
+use crate::gamemap::GameMap;
+use crate::lighting::{self, Lightmap};
+use crate::texture;
+
+// Bakes the static lights of each level into a lightmap next to the level file. The game
+// picks it up the next time the level is loaded.
+pub fn run(levels: &[String]) -> Result<(), String> {
+    let textures = texture::load_manifest(texture::MANIFEST_PATH)?.builtin;
+    for level in levels {
+        let map = GameMap::load_map(level, &textures);
+        if map.static_lights.is_empty() {
+            println!("{}: no static lights, nothing to bake", level);
+            continue;
+        }
+
+        let start = Instant::now();
+        let lightmap = Lightmap::bake(&map);
+        let path = lighting::lightmap_path(level);
+        lightmap.save(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        println!(
+            "{}: baked {} lights into {} in {:.2}s",
+            level,
+            map.static_lights.len(),
+            path.display(),
+            start.elapsed().as_secs_f32()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+use raylib::prelude::*;
+
+use crate::font::Font;
+use crate::game::{Difficulty, Game};
+use crate::hud::Hud;
+use crate::input::InputFrame;
+use crate::raycaster::Raycaster;
+use crate::settings::Settings;
+use crate::texture::{self, Manifest};
+use crate::weapon;
+
+const TURN_PER_FRAME: f32 = std::f32::consts::TAU / 240.0; // One full turn every 240 frames
+
+// Renders `frames` frames into the software framebuffer without opening a window, turning
+// on the spot so every direction of the level is drawn, then prints the frame timings and
+// the final state hash
+pub fn run(
+    settings: &Settings,
+    textures: Vec<Rc<RefCell<Image>>>,
+    manifest: Manifest,
+    level_path: &str,
+    difficulty: Difficulty,
+    seed: u64,
+    frames: usize,
+) -> Result<(), String> {
+    let font = Rc::new(Font::load("res/font.txt"));
+    let hud = Hud::load("res/hud.txt", textures.clone(), manifest.builtin.face, font);
+    let weapons = weapon::load_weapons("res/weapons.txt")?;
+
+    let world = Game::load_level(level_path, &weapons, &manifest.builtin, settings, difficulty, seed);
+    let mut raycaster = Raycaster::new(
+        settings.buffer_width,
+        settings.buffer_height,
+        None,
+        Rc::clone(&world.player),
+        texture::mip_chains(&textures),
+        manifest.animations,
+        Rc::clone(&world.game_map),
+    );
+    raycaster.set_texture_filter(settings.texture_filter);
+
+    let input = InputFrame { turn: TURN_PER_FRAME, ..InputFrame::default() };
+    let mut timings = Vec::with_capacity(frames);
+    let start = Instant::now();
+
+    // The world ticks once a frame, so the frame number is the tick
+    for frame in 0..frames {
+        let frame_start = Instant::now();
+
+        raycaster.render_frame(frame);
+        {
+            let mut canvas = raycaster.canvas();
+            let player = world.player.borrow();
+            hud.draw_view(&mut canvas, &player);
+            hud.draw(&mut canvas, &player);
+        }
+        world.tick(&input);
+
+        timings.push(frame_start.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    let total = start.elapsed().as_secs_f64();
+    if timings.is_empty() {
+        println!("No frames rendered");
+        return Ok(());
+    }
+
+    let average = timings.iter().sum::<f64>() / timings.len() as f64;
+    timings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let percentile = |p: f64| timings[((timings.len() - 1) as f64 * p).round() as usize];
+
+    println!("Rendered {} frames of {} at {}x{} in {:.2} s", frames, level_path, settings.buffer_width, settings.buffer_height, total);
+    println!("  average {:8.3} ms  ({:.0} fps)", average, 1000.0 / average);
+    println!("  min     {:8.3} ms", timings[0]);
+    println!("  p50     {:8.3} ms", percentile(0.5));
+    println!("  p95     {:8.3} ms", percentile(0.95));
+    println!("  p99     {:8.3} ms", percentile(0.99));
+    println!("  max     {:8.3} ms", timings[timings.len() - 1]);
+    println!("State hash {:016x} (seed {})", world.state_hash(), seed);
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use raylib::prelude::*;
+
+pub fn color_to_u32(color: Color) -> u32 {
+    // Swap red and blue channels for BGRA format
+    ((color.a as u32) << 24) | ((color.b as u32) << 16) | ((color.g as u32) << 8) | (color.r as u32)
+}
+
+// A view of the software framebuffer that overlays (HUD, text, maps) draw into.
+// Coordinates are screen coordinates with y pointing down, while the pixelbuffer itself
+// is stored bottom row first and flipped when it is presented.
+pub struct Canvas<'a> {
+    pub pixels: &'a mut [u32],
+    pub width: i32,
+    pub height: i32,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(pixels: &'a mut [u32], width: i32, height: i32) -> Self {
+        Canvas { pixels, width, height }
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        ((self.height - 1 - y) * self.width + x) as usize
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        for py in y.max(0)..(y + height).min(self.height) {
+            for px in x.max(0)..(x + width).min(self.width) {
+                let index = self.index(px, py);
+                self.pixels[index] = color_to_u32(color);
+            }
+        }
+    }
+
+    // Darkens everything drawn so far, used to put menus over the frozen game view
+    pub fn shade(&mut self, factor: f32) {
+        for pixel in self.pixels.iter_mut() {
+            let channel = |shift: u32| ((((*pixel >> shift) & 0xFF) as f32 * factor) as u32) << shift;
+            *pixel = (*pixel & 0xFF00_0000) | channel(16) | channel(8) | channel(0);
+        }
+    }
+
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+
+        loop {
+            if x >= 0 && x < self.width && y >= 0 && y < self.height {
+                let index = self.index(x, y);
+                self.pixels[index] = color_to_u32(color);
+            }
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // Draws `image` scaled to `width` x `height`, skipping transparent and pure black pixels
+    // the same way the sprite renderer does
+    pub fn draw_image(&mut self, image: &Rc<RefCell<Image>>, x: i32, y: i32, width: i32, height: i32) {
+        // Read the size first, the borrows would otherwise live until draw_image_region borrows mutably
+        let (image_width, image_height) = (image.borrow().width, image.borrow().height);
+        self.draw_image_region(image, 0, 0, image_width, image_height, x, y, width, height);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_image_region(
+        &mut self,
+        image: &Rc<RefCell<Image>>,
+        src_x: i32,
+        src_y: i32,
+        src_width: i32,
+        src_height: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+
+        let mut image = image.borrow_mut();
+
+        for py in y.max(0)..(y + height).min(self.height) {
+            let tex_y = src_y + (py - y) * src_height / height;
+            for px in x.max(0)..(x + width).min(self.width) {
+                let tex_x = src_x + (px - x) * src_width / width;
+
+                let color = image.get_color(tex_x, tex_y);
+                if color.a > 0 && !(color.r == 0 && color.g == 0 && color.b == 0) {
+                    let index = self.index(px, py);
+                    self.pixels[index] = color_to_u32(color);
+                }
+            }
+        }
+    }
+}
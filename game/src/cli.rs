@@ -0,0 +1,168 @@
+use crate::game::Difficulty;
+
+pub const USAGE: &str = "\
+Usage: game [OPTIONS] [LEVEL]
+       game bake [LEVEL | --campaign <PATH>]
+
+Bake writes a lightmap for the static lights of each level next to the level file.
+
+Options:
+  --level <PATH>          Level file to play (default res/level_1.txt)
+  --campaign <PATH>       Text file listing level files to play in order
+  --window <WxH>          Window size, e.g. 1280x720
+  --fullscreen            Start in fullscreen
+  --resolution <WxH>      Internal render resolution, e.g. 320x200
+  --seed <N>              Seed for gameplay randomness (random by default)
+  --difficulty <LEVEL>    easy, normal or hard
+  --record <PATH>         Record a demo of the first level played to PATH
+  --play <PATH>           Play back the demo at PATH, hold Right to fast-forward
+  --verify <PATH>         Replay the demo at PATH without a window and check its end state
+  --skip-menu             Start playing straight away
+  --benchmark [FRAMES]    Render FRAMES frames without a window and print timings
+  --help                  Show this message";
+
+const DEFAULT_BENCHMARK_FRAMES: usize = 600;
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Options {
+    pub level: Option<String>,
+    pub campaign: Option<String>,
+    pub window: Option<(i32, i32)>,
+    pub fullscreen: bool,
+    pub resolution: Option<(i32, i32)>,
+    pub seed: Option<u64>,
+    pub difficulty: Difficulty,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub verify: Option<String>,
+    pub skip_menu: bool,
+    pub benchmark: Option<usize>, // Number of frames to render headless
+    pub bake: bool,
+    pub help: bool,
+}
+
+fn parse_size(flag: &str, value: &str) -> Result<(i32, i32), String> {
+    let parsed = value
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse::<i32>().ok()?, height.parse::<i32>().ok()?)));
+
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("{} expects a size like 640x400, got '{}'", flag, value)),
+    }
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+
+    while let Some(arg) = args.next() {
+        // Flags may also be written as --flag=value
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let value = |args: &mut dyn Iterator<Item = String>| -> Result<String, String> {
+            inline_value.clone().or_else(|| args.next()).ok_or(format!("{} needs a value", flag))
+        };
+
+        match flag.as_str() {
+            "--level" => options.level = Some(value(&mut args)?),
+            "--campaign" => options.campaign = Some(value(&mut args)?),
+            "--window" => options.window = Some(parse_size(&flag, &value(&mut args)?)?),
+            "--fullscreen" => options.fullscreen = true,
+            "--resolution" => options.resolution = Some(parse_size(&flag, &value(&mut args)?)?),
+            "--seed" => {
+                let seed = value(&mut args)?;
+                options.seed = Some(seed.parse().map_err(|_| format!("--seed expects a number, got '{}'", seed))?);
+            }
+            "--difficulty" => {
+                let name = value(&mut args)?;
+                options.difficulty = Difficulty::from_name(&name).ok_or(format!("Unknown difficulty '{}'", name))?;
+            }
+            "--record" => options.record = Some(value(&mut args)?),
+            "--play" => options.play = Some(value(&mut args)?),
+            "--verify" => options.verify = Some(value(&mut args)?),
+            "--skip-menu" => options.skip_menu = true,
+            "--benchmark" => {
+                // The frame count is optional, so only a following number is taken as one
+                let frames = match inline_value {
+                    Some(frames) => Some(frames),
+                    None => args.next_if(|next| next.parse::<usize>().is_ok()),
+                };
+                options.benchmark = Some(match frames {
+                    Some(frames) => frames.parse().map_err(|_| format!("--benchmark expects a frame count, got '{}'", frames))?,
+                    None => DEFAULT_BENCHMARK_FRAMES,
+                });
+            }
+            "--help" | "-h" => options.help = true,
+            "bake" if !options.bake && options.level.is_none() => options.bake = true,
+            _ if !flag.starts_with('-') && options.level.is_none() => options.level = Some(arg),
+            _ => return Err(format!("Unknown argument '{}'", arg)),
+        }
+    }
+
+    if options.level.is_some() && options.campaign.is_some() {
+        return Err("--level and --campaign can't be used together".to_string());
+    }
+    if options.record.is_some() && (options.play.is_some() || options.verify.is_some()) {
+        return Err("--record can't be used with --play or --verify".to_string());
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Options, String> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn accepted_arguments() {
+        let cases = [
+            ("", Options::default()),
+            ("res/level_2.txt", Options { level: Some("res/level_2.txt".into()), ..Options::default() }),
+            ("--level res/level_2.txt", Options { level: Some("res/level_2.txt".into()), ..Options::default() }),
+            ("--level=res/level_2.txt", Options { level: Some("res/level_2.txt".into()), ..Options::default() }),
+            ("--window=1280x720 --resolution 320x200", Options { window: Some((1280, 720)), resolution: Some((320, 200)), ..Options::default() }),
+            ("--seed=12 --difficulty hard", Options { seed: Some(12), difficulty: Difficulty::Hard, ..Options::default() }),
+            ("--benchmark", Options { benchmark: Some(DEFAULT_BENCHMARK_FRAMES), ..Options::default() }),
+            ("--benchmark 100", Options { benchmark: Some(100), ..Options::default() }),
+            ("--benchmark=100", Options { benchmark: Some(100), ..Options::default() }),
+            // A level after a bare --benchmark isn't taken as the frame count
+            ("--benchmark res/level_2.txt", Options { benchmark: Some(DEFAULT_BENCHMARK_FRAMES), level: Some("res/level_2.txt".into()), ..Options::default() }),
+            ("bake", Options { bake: true, ..Options::default() }),
+            ("bake res/level_2.txt", Options { bake: true, level: Some("res/level_2.txt".into()), ..Options::default() }),
+            ("bake --campaign res/campaign.txt", Options { bake: true, campaign: Some("res/campaign.txt".into()), ..Options::default() }),
+            ("--fullscreen --skip-menu -h", Options { fullscreen: true, skip_menu: true, help: true, ..Options::default() }),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse_args(args), Ok(expected), "{}", args);
+        }
+    }
+
+    #[test]
+    fn rejected_arguments() {
+        let cases = [
+            ("--window 1280", "--window expects a size like 640x400, got '1280'"),
+            ("--resolution=0x200", "--resolution expects a size like 640x400, got '0x200'"),
+            ("--window -640x400", "--window expects a size like 640x400, got '-640x400'"),
+            ("--fast", "Unknown argument '--fast'"),
+            ("level_1.txt level_2.txt", "Unknown argument 'level_2.txt'"),
+            ("res/level_2.txt bake", "Unknown argument 'bake'"),
+            ("--level", "--level needs a value"),
+            ("--seed", "--seed needs a value"),
+            ("--seed ten", "--seed expects a number, got 'ten'"),
+            ("--difficulty insane", "Unknown difficulty 'insane'"),
+            ("--benchmark=lots", "--benchmark expects a frame count, got 'lots'"),
+            ("--level a.txt --campaign b.txt", "--level and --campaign can't be used together"),
+            ("--record a.txt --play b.txt", "--record can't be used with --play or --verify"),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse_args(args), Err(expected.to_string()), "{}", args);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::Path;
+
+use crate::game::{Difficulty, Game, World};
+use crate::input::InputFrame;
+use crate::settings::Settings;
+use crate::texture::{self, BuiltinTextures};
+use crate::weapon::{self, WeaponDef};
+
+// The end hash is taken over the save format, so changes to either invalidate old demos
+const DEMO_VERSION: u32 = 2;
+const HEADER: &str = "RAYCASTER_DEMO";
+
+// A recorded level: where and how it started, then the input of every world tick. Replaying
+// the ticks from the same start always ends in the same state, which `end_hash` records.
+#[derive(Clone)]
+pub struct Demo {
+    pub level: String,
+    pub difficulty: Difficulty,
+    pub seed: u64,
+    pub fov: f32, // The camera plane is part of the world state, so the hash depends on it
+    pub ticks: Vec<InputFrame>,
+    pub end_hash: Option<u64>,
+}
+
+impl Demo {
+    pub fn new(level: &str, difficulty: Difficulty, seed: u64, fov: f32) -> Self {
+        Demo { level: level.to_string(), difficulty, seed, fov, ticks: Vec::new(), end_hash: None }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(Path::new(path)).map_err(|error| error.to_string())?;
+        let demo = Self::parse(&content)?;
+        if !Path::new(&demo.level).exists() {
+            return Err(format!("Level '{}' not found", demo.level));
+        }
+        Ok(demo)
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let mut lines = content.lines();
+
+        let version = lines
+            .next()
+            .and_then(|header| header.strip_prefix(HEADER))
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or("Not a demo file")?;
+        if version != DEMO_VERSION {
+            return Err(format!("Demo version {} is not supported (expected {})", version, DEMO_VERSION));
+        }
+
+        let mut demo = Demo::new("", Difficulty::default(), 0, Settings::default().fov);
+        let mut section = "";
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line;
+                continue;
+            }
+
+            if section == "[TICKS]" {
+                demo.ticks.push(parse_tick(line).ok_or(format!("Bad tick '{}'", line))?);
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("Bad line '{}'", line));
+            };
+            let value = value.trim();
+            let bad_value = || format!("Bad value '{}' for '{}'", value, key.trim());
+            match key.trim() {
+                "level" => demo.level = value.to_string(),
+                "difficulty" => demo.difficulty = Difficulty::from_name(value).ok_or_else(bad_value)?,
+                "seed" => demo.seed = value.parse().map_err(|_| bad_value())?,
+                "fov" => demo.fov = value.parse().map_err(|_| bad_value())?,
+                "end_hash" => demo.end_hash = Some(u64::from_str_radix(value, 16).map_err(|_| bad_value())?),
+                _ => {}
+            }
+        }
+        Ok(demo)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut content = format!("{} {}\n\n[DEMO]\n", HEADER, DEMO_VERSION);
+        content += &format!("level = {}\n", self.level);
+        content += &format!("difficulty = {}\n", self.difficulty.name());
+        content += &format!("seed = {}\n", self.seed);
+        content += &format!("fov = {}\n", self.fov);
+        if let Some(hash) = self.end_hash {
+            content += &format!("end_hash = {:016x}\n", hash);
+        }
+
+        // One line per tick: held actions, pressed actions, turn, weapon slot (0 for none), look
+        content += "\n[TICKS]\n";
+        for tick in &self.ticks {
+            let slot = tick.weapon_slot.unwrap_or(0);
+            content += &format!("{}, {}, {}, {}, {}\n", tick.held, tick.pressed, tick.turn, slot, tick.look);
+        }
+
+        fs::write(Path::new(path), content).map_err(|error| error.to_string())
+    }
+
+    // The level as it was when recording started
+    pub fn load_world(&self, weapons: &[WeaponDef], textures: &BuiltinTextures) -> World {
+        let settings = Settings { fov: self.fov, ..Settings::default() };
+        Game::load_level(&self.level, weapons, textures, &settings, self.difficulty, self.seed)
+    }
+}
+
+fn parse_tick(line: &str) -> Option<InputFrame> {
+    let values: Vec<&str> = line.split(',').map(|value| value.trim()).collect();
+    let [held, pressed, turn, slot, look] = values[..] else {
+        return None;
+    };
+    let slot: usize = slot.parse().ok()?;
+
+    Some(InputFrame {
+        held: held.parse().ok()?,
+        pressed: pressed.parse().ok()?,
+        turn: turn.parse().ok()?,
+        look: look.parse().ok()?,
+        weapon_slot: if slot == 0 { None } else { Some(slot) },
+    })
+}
+
+// Replays the demo without a window and compares the end state with the recorded hash
+pub fn verify(path: &str) -> Result<(), String> {
+    let demo = Demo::load(path)?;
+    let expected = demo.end_hash.ok_or("Demo has no end state hash")?;
+
+    let manifest = texture::load_manifest(texture::MANIFEST_PATH)?;
+    let world = demo.load_world(&weapon::load_weapons("res/weapons.txt")?, &manifest.builtin);
+    for tick in &demo.ticks {
+        world.tick(tick);
+    }
+
+    let hash = world.state_hash();
+    if hash != expected {
+        return Err(format!("Desync after {} ticks: expected {:016x}, got {:016x}", demo.ticks.len(), expected, hash));
+    }
+    println!("{}: {} ticks, end state {:016x} matches", path, demo.ticks.len(), hash);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Action;
+
+    #[test]
+    fn recorded_demo_replays_to_the_same_state() {
+        let weapons = weapon::load_weapons("res/weapons.txt").unwrap();
+        let textures = texture::load_manifest(texture::MANIFEST_PATH).unwrap().builtin;
+        let mut demo = Demo::new("res/level_1.txt", Difficulty::Hard, 42, 0.8);
+        let world = demo.load_world(&weapons, &textures);
+        for tick in 0..120 {
+            let frame = InputFrame {
+                held: if tick % 3 == 0 { Action::StrafeLeft.bit() } else { Action::MoveForward.bit() },
+                pressed: if tick == 30 { Action::Fire.bit() } else { 0 },
+                turn: -0.013 * (tick % 7) as f32,
+                look: 0.001,
+                weapon_slot: if tick == 60 { Some(3) } else { None },
+            };
+            demo.ticks.push(frame);
+            world.tick(&frame);
+        }
+        demo.end_hash = Some(world.state_hash());
+
+        let path = std::env::temp_dir().join(format!("demo_test_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        demo.save(path).unwrap();
+        let loaded = Demo::load(path);
+        let verified = verify(path);
+        fs::remove_file(path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.ticks, demo.ticks);
+        let replay = loaded.load_world(&weapons, &textures);
+        for tick in &loaded.ticks {
+            replay.tick(tick);
+        }
+        assert_eq!(Some(replay.state_hash()), demo.end_hash);
+        assert_eq!(verified, Ok(()));
+    }
+
+    #[test]
+    fn malformed_ticks_are_refused() {
+        let header = format!("{} {}\n\n[DEMO]\nlevel = res/level_1.txt\n\n[TICKS]\n1, 0, 0.5, 0, 0\n", HEADER, DEMO_VERSION);
+        assert_eq!(Demo::parse(&header).unwrap().ticks.len(), 1);
+
+        for tick in ["1, 0, 0.5, 0", "1, 0, 0.5, 0, 0, 0", "1, 0, fast, 0, 0", "-1, 0, 0.5, 0, 0"] {
+            let error = Demo::parse(&format!("{}{}\n", header, tick)).err().unwrap();
+            assert_eq!(error, format!("Bad tick '{}'", tick));
+        }
+    }
+}
@@ -2,8 +2,9 @@ use raylib::prelude::*;
 use std::rc::Rc;
 use std::cell::RefCell;
 
-use crate::GameMap;
+use crate::gamemap::GameMap;
 use crate::gamemap::Sprite;
+use crate::save::SaveData;
 
 #[derive(Clone)]
 pub struct Enemy {
@@ -14,6 +15,7 @@ pub struct Enemy {
     _map: Rc<RefCell<GameMap>>,
     sprite_index: usize,
     frame_counter: usize, 
+    pub is_dead: bool,
 }
 
 impl Enemy {
@@ -34,9 +36,11 @@ impl Enemy {
             dir_x: dir.x as f64,
             dir_y: dir.y as f64,
             is_projectile: 0.0,
-            is_ui: 0.0,
             is_destroyed: 0.0,
             texture: 13,
+            is_hidden: false,
+            health: 100.0,
+            damage: 0.0,
         };
 
         let sprite_index = map.sprites.len();
@@ -50,18 +54,54 @@ impl Enemy {
             _map: _map.clone(),
             sprite_index,
             frame_counter: 0, 
+            is_dead: false,
         }
     }
 
     pub fn update(&mut self) {
+        if self.is_dead {
+            return;
+        }
+
         self.frame_counter += 1;
 
+        let mut map = self._map.borrow_mut();
+        let Some(sprite) = map.sprites.get_mut(self.sprite_index) else {
+            return;
+        };
+
+        if sprite.health <= 0.0 {
+            // There is no death animation yet, so the body just disappears
+            self.is_dead = true;
+            sprite.is_hidden = true;
+            return;
+        }
+
         // Change texture every 10 frames (adjust for desired speed)
-        if self.frame_counter % 10 == 0 {
-            let mut map = self._map.borrow_mut();
-            if let Some(sprite) = map.sprites.get_mut(self.sprite_index) {
-                sprite.texture = 13 + ((sprite.texture - 13 + 1) % 4);
-            }
+        if self.frame_counter.is_multiple_of(10) {
+            sprite.texture = 13 + ((sprite.texture - 13 + 1) % 4);
         }
     }
+
+    pub fn save_state(&self, data: &mut SaveData) {
+        data.section("ENEMY");
+        data.put_list("pos", &[self.pos.x, self.pos.y]);
+        data.put_list("dir", &[self.dir.x, self.dir.y]);
+        data.put_list("projection", &[self.projection.x, self.projection.y]);
+        data.put("movespeed", self.movespeed);
+        data.put("sprite_index", self.sprite_index);
+        data.put("frame_counter", self.frame_counter);
+        data.put("is_dead", self.is_dead);
+    }
+
+    pub fn load_state(&mut self, data: &SaveData) -> Result<(), String> {
+        self.pos = data.parse_vector("ENEMY", "pos")?;
+        self.dir = data.parse_vector("ENEMY", "dir")?;
+        self.projection = data.parse_vector("ENEMY", "projection")?;
+        self.movespeed = data.parse("ENEMY", "movespeed")?;
+        self.sprite_index = data.parse("ENEMY", "sprite_index")?;
+        self.frame_counter = data.parse("ENEMY", "frame_counter")?;
+        self.is_dead = data.parse("ENEMY", "is_dead")?;
+        Ok(())
+    }
 }
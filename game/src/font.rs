@@ -0,0 +1,176 @@
+use std::fs;
+use std::path::Path;
+
+use raylib::prelude::*;
+
+use crate::canvas::Canvas;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextAlign {
+    Left,
+    Center, // x is the centre of each line
+    Right, // x is the right edge of each line
+}
+
+// A bitmap font drawn straight into the software framebuffer. The glyph atlas is read
+// once at load time, so drawing never touches raylib and works without a window.
+pub struct Font {
+    cell_width: i32,
+    cell_height: i32,
+    columns: i32,
+    first_char: u32,
+    line_height: i32,
+    advances: Vec<i32>, // Indexed by character code minus first_char
+    atlas_width: i32,
+    coverage: Vec<bool>, // One entry per atlas pixel, true where the glyph is solid
+}
+
+impl Font {
+    pub fn load(file_path: &str) -> Self {
+        let content = fs::read_to_string(Path::new(file_path))
+            .expect("Failed to read font metrics file");
+
+        let mut image_path = String::new();
+        let mut cell_width = 8;
+        let mut cell_height = 8;
+        let mut columns = 16;
+        let mut first_char = 32;
+        let mut line_height = 0;
+        let mut advance_entries = Vec::new();
+        let mut section = String::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                section = line.to_string();
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            if section == "[ADVANCES]" {
+                if let (Ok(code), Ok(advance)) = (key.parse::<u32>(), value.parse::<i32>()) {
+                    advance_entries.push((code, advance));
+                }
+                continue;
+            }
+
+            match key {
+                "image" => image_path = value.to_string(),
+                "cell_width" => cell_width = value.parse().unwrap_or(8),
+                "cell_height" => cell_height = value.parse().unwrap_or(8),
+                "columns" => columns = value.parse().unwrap_or(16),
+                "first_char" => first_char = value.parse().unwrap_or(32),
+                "line_height" => line_height = value.parse().unwrap_or(0),
+                _ => eprintln!("Unknown font property '{}' in {}", key, file_path),
+            }
+        }
+
+        let image = Image::load_image(&image_path).expect("Failed to load font image");
+        let coverage = image.get_image_data().iter().map(|color| color.a > 127).collect();
+
+        let rows = image.height / cell_height;
+        let glyph_count = (columns * rows).max(0) as usize;
+        let mut advances = vec![cell_width; glyph_count];
+        for (code, advance) in advance_entries {
+            if let Some(slot) = code.checked_sub(first_char).and_then(|i| advances.get_mut(i as usize)) {
+                *slot = advance;
+            }
+        }
+
+        Font {
+            cell_width,
+            cell_height,
+            columns,
+            first_char,
+            line_height: if line_height > 0 { line_height } else { cell_height },
+            advances,
+            atlas_width: image.width,
+            coverage,
+        }
+    }
+
+    pub fn line_height(&self, scale: i32) -> i32 {
+        self.line_height * scale
+    }
+
+    fn glyph_index(&self, c: char) -> Option<usize> {
+        let index = (c as u32).checked_sub(self.first_char)? as usize;
+        (index < self.advances.len()).then_some(index)
+    }
+
+    fn advance(&self, c: char) -> i32 {
+        self.glyph_index(c).map_or(self.cell_width, |index| self.advances[index])
+    }
+
+    // Width of the widest line of `text`
+    pub fn measure(&self, text: &str, scale: i32) -> i32 {
+        text.lines()
+            .map(|line| line.chars().map(|c| self.advance(c)).sum::<i32>() * scale)
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(&self, canvas: &mut Canvas, text: &str, x: i32, y: i32, scale: i32, color: Color, align: TextAlign) {
+        for (row, line) in text.lines().enumerate() {
+            let width = self.measure(line, scale);
+            let mut pen_x = match align {
+                TextAlign::Left => x,
+                TextAlign::Center => x - width / 2,
+                TextAlign::Right => x - width,
+            };
+            let pen_y = y + row as i32 * self.line_height(scale);
+
+            for c in line.chars() {
+                self.draw_glyph(canvas, c, pen_x, pen_y, scale, color);
+                pen_x += self.advance(c) * scale;
+            }
+        }
+    }
+
+    pub fn wrap(&self, text: &str, max_width: i32, scale: i32) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for paragraph in text.lines() {
+            let mut line = String::new();
+
+            for word in paragraph.split_whitespace() {
+                let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+                if self.measure(&candidate, scale) <= max_width || line.is_empty() {
+                    line = candidate;
+                } else {
+                    lines.push(std::mem::replace(&mut line, word.to_string()));
+                }
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    fn draw_glyph(&self, canvas: &mut Canvas, c: char, x: i32, y: i32, scale: i32, color: Color) {
+        let Some(index) = self.glyph_index(c) else {
+            return;
+        };
+
+        let cell_x = (index as i32 % self.columns) * self.cell_width;
+        let cell_y = (index as i32 / self.columns) * self.cell_height;
+
+        for gy in 0..self.cell_height {
+            for gx in 0..self.cell_width {
+                let atlas_index = ((cell_y + gy) * self.atlas_width + cell_x + gx) as usize;
+                if self.coverage.get(atlas_index) == Some(&true) {
+                    canvas.fill_rect(x + gx * scale, y + gy * scale, scale, scale, color);
+                }
+            }
+        }
+    }
+}
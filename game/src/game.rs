@@ -0,0 +1,878 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use raylib::prelude::*;
+
+use crate::automap::Automap;
+use crate::cli::Options;
+use crate::demo::Demo;
+use crate::enemy::Enemy;
+use crate::font::{Font, TextAlign};
+use crate::gamemap::{GameMap, PickupKind};
+use crate::hud::Hud;
+use crate::input::{Action, Binding, InputFrame, MOUSE_BUTTONS, REBINDABLE};
+use crate::menu::{Menu, MenuEvent, MenuItem};
+use crate::player::Player;
+use crate::raycaster::Raycaster;
+use crate::rng::{self, GameRng};
+use crate::save::{self, SaveData, QUICKSAVE_SLOT};
+use crate::settings::Settings;
+use crate::texture::{self, Animation, BuiltinTextures, Manifest, MipChain, TEXTURE_FILTERS};
+use crate::weapon::{self, WeaponDef};
+
+// Internal resolutions offered on the options screen
+const RESOLUTIONS: [(i32, i32); 5] = [(320, 200), (400, 250), (550, 350), (640, 400), (800, 500)];
+const OPTION_VALUES: usize = 5; // Options entries before the key bindings
+const BACKGROUND_COLOR: Color = Color::new(24, 20, 20, 255);
+const MENU_SHADE: f32 = 0.35;
+const DEFAULT_LEVEL: &str = "res/level_1.txt";
+const SAVE_SLOTS: usize = 6;
+const ATTRACT_DEMOS: [&str; 1] = ["res/demo_1.txt"]; // Played in turn while the title screen is idle
+const ATTRACT_DELAY: usize = 600; // Title screen frames before a demo starts
+const FAST_FORWARD_TICKS: usize = 8; // World ticks per frame while fast-forwarding a demo
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    fn enemy_health_scale(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+        }
+    }
+
+    fn ammo_scale(self) -> i32 {
+        if self == Difficulty::Easy { 2 } else { 1 }
+    }
+}
+
+// Levels to play in order, a campaign file lists one level path per line
+pub fn level_list(options: &Options) -> Vec<String> {
+    if let Some(campaign) = &options.campaign {
+        let content = fs::read_to_string(Path::new(campaign))
+            .expect("Failed to read campaign file");
+        let levels: Vec<String> = content
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect();
+        assert!(!levels.is_empty(), "No levels in {}", campaign);
+        return levels;
+    }
+
+    vec![options.level.clone().unwrap_or(DEFAULT_LEVEL.to_string())]
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GameState {
+    Title,
+    MainMenu,
+    Playing,
+    Paused,
+    Options,
+    SaveMenu,
+    LoadMenu,
+    GameOver,
+    Intermission,
+    Demo,
+}
+
+// Everything that is rebuilt when a level (re)starts
+pub struct World {
+    pub game_map: Rc<RefCell<GameMap>>,
+    pub player: Rc<RefCell<Player>>,
+    pub enemy: Rc<RefCell<Enemy>>,
+}
+
+impl World {
+    // One simulation step, the only place the world changes during play
+    pub fn tick(&self, input: &InputFrame) {
+        self.player.borrow_mut().update(input);
+        self.enemy.borrow_mut().update();
+        self.game_map.borrow_mut().update_projectiles();
+
+        let player = self.player.borrow();
+        self.game_map.borrow_mut().explore_view(
+            player.pos.x as f64,
+            player.pos.y as f64,
+            (player.dir.x as f64, player.dir.y as f64),
+            (player.projection.x as f64, player.projection.y as f64),
+        );
+    }
+
+    pub fn save_state(&self, data: &mut SaveData) {
+        self.game_map.borrow().save_state(data);
+        self.player.borrow().save_state(data);
+        self.enemy.borrow().save_state(data);
+    }
+
+    pub fn load_state(&self, data: &SaveData) -> Result<(), String> {
+        self.game_map.borrow_mut().load_state(data)?;
+        self.player.borrow_mut().load_state(data)?;
+        self.enemy.borrow_mut().load_state(data)
+    }
+
+    // Runs with the same seed and the same inputs must end with the same hash
+    pub fn state_hash(&self) -> u64 {
+        let mut data = SaveData::default();
+        self.save_state(&mut data);
+        data.hash()
+    }
+}
+
+// A demo being played back in the window
+struct Playback {
+    demo: Demo,
+    tick: usize, // Next tick to replay
+    attract: bool, // Started by the idle title screen rather than --play
+}
+
+pub struct Game {
+    pub state: GameState,
+    pub quit: bool,
+    menu_return: GameState, // Screen the options and save slot menus go back to
+    slots: Vec<String>, // Save slots listed by the save or load menu
+    slot_labels: Vec<String>, // What each listed slot holds, read when the menu opens
+    menu: Menu,
+    rebinding: Option<Action>, // Options entry waiting for a key or button
+    frame_counter: usize,
+    idle_frames: usize, // Frames the title screen has waited without input
+    attract_index: usize, // Next entry of ATTRACT_DEMOS
+    record_path: Option<String>, // Where --record writes the demo, until recording starts
+    recording: Option<(String, Demo)>,
+    playback: Option<Playback>,
+    pub settings: Settings,
+    settings_path: PathBuf,
+    resolution: Option<(i32, i32)>, // From the command line, used over the saved one until it's changed in game
+    audio: Option<RaylibAudio>,
+    textures: Rc<Vec<MipChain>>, // What the raycaster samples, the HUD draws straight from the images
+    animations: Vec<Animation>,
+    builtin_textures: BuiltinTextures,
+    font: Rc<Font>,
+    hud: Hud,
+    automap: Automap,
+    weapons: Vec<WeaponDef>,
+    difficulty: Difficulty,
+    levels: Vec<String>,
+    level_index: usize,
+    level_ticks: usize,
+    seed: u64, // Every level starts its random rolls from this
+    world: World,
+    raycaster: Raycaster,
+}
+
+impl Game {
+    pub fn new(
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        settings: Settings,
+        settings_path: PathBuf,
+        textures: Vec<Rc<RefCell<Image>>>,
+        manifest: Manifest,
+        options: &Options,
+    ) -> Result<Self, String> {
+        let font = Rc::new(Font::load("res/font.txt"));
+        let hud = Hud::load("res/hud.txt", textures.clone(), manifest.builtin.face, font.clone());
+        let weapons = weapon::load_weapons("res/weapons.txt")?;
+        let textures = texture::mip_chains(&textures);
+
+        // The game still runs without sound if there is no audio device
+        let audio = RaylibAudio::init_audio_device().ok();
+        if let Some(audio) = &audio {
+            audio.set_master_volume(settings.volume);
+        }
+
+        let levels = level_list(options);
+        let seed = options.seed.unwrap_or_else(rng::random_seed);
+        let world = Self::load_level(&levels[0], &weapons, &manifest.builtin, &settings, options.difficulty, seed);
+        let resolution = options.resolution;
+        let buffer_size = resolution.unwrap_or((settings.buffer_width, settings.buffer_height));
+        let raycaster = Self::create_raycaster(rl, thread, &settings, buffer_size, &textures, &manifest.animations, &world);
+
+        let mut game = Game {
+            state: GameState::Title,
+            quit: false,
+            menu_return: GameState::MainMenu,
+            slots: Vec::new(),
+            slot_labels: Vec::new(),
+            menu: Menu::default(),
+            rebinding: None,
+            frame_counter: 0,
+            idle_frames: 0,
+            attract_index: 0,
+            record_path: options.record.clone(),
+            recording: None,
+            playback: None,
+            settings,
+            settings_path,
+            resolution,
+            audio,
+            textures,
+            animations: manifest.animations,
+            builtin_textures: manifest.builtin,
+            font,
+            hud,
+            automap: Automap::default(),
+            weapons,
+            difficulty: options.difficulty,
+            levels,
+            level_index: 0,
+            level_ticks: 0,
+            seed,
+            world,
+            raycaster,
+        };
+        if let Some(path) = &options.play {
+            match Demo::load(path) {
+                Ok(demo) => game.start_demo(rl, thread, demo, false),
+                Err(error) => {
+                    eprintln!("Can't play {}: {}", path, error);
+                    game.set_state(rl, GameState::Title);
+                }
+            }
+        } else if options.skip_menu {
+            game.begin_recording();
+            game.set_state(rl, GameState::Playing);
+        } else {
+            game.set_state(rl, GameState::Title);
+        }
+        Ok(game)
+    }
+
+    pub fn load_level(
+        level_path: &str,
+        weapons: &[WeaponDef],
+        textures: &BuiltinTextures,
+        settings: &Settings,
+        difficulty: Difficulty,
+        seed: u64,
+    ) -> World {
+        let game_map = Rc::new(RefCell::new(GameMap::load_map(level_path, textures)));
+        game_map.borrow_mut().rng = GameRng::new(seed);
+        let player = Rc::new(RefCell::new(Player::new(game_map.clone(), weapons.to_vec())));
+        player.borrow_mut().set_fov(settings.fov);
+        let enemy = Rc::new(RefCell::new(Enemy::new(game_map.clone())));
+
+        {
+            let mut map = game_map.borrow_mut();
+            map.load_lightmap(level_path);
+            for sprite in map.sprites.iter_mut().filter(|sprite| sprite.health > 0.0) {
+                sprite.health *= difficulty.enemy_health_scale();
+            }
+            for pickup in map.pickups.iter_mut() {
+                if let PickupKind::Ammo(kind, amount) = pickup.kind {
+                    pickup.kind = PickupKind::Ammo(kind, amount * difficulty.ammo_scale());
+                }
+            }
+        }
+
+        for warning in game_map.borrow().validate(player.borrow().pos) {
+            eprintln!("level warning: {}", warning);
+        }
+
+        World { game_map, player, enemy }
+    }
+
+    pub fn create_raycaster(
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        settings: &Settings,
+        (buffer_width, buffer_height): (i32, i32),
+        textures: &Rc<Vec<MipChain>>,
+        animations: &[Animation],
+        world: &World,
+    ) -> Raycaster {
+        let framebuffer = rl
+            .load_render_texture(thread, buffer_width as u32, buffer_height as u32)
+            .expect("Failed to create the framebuffer");
+
+        let mut raycaster = Raycaster::new(
+            buffer_width,
+            buffer_height,
+            Some(framebuffer),
+            Rc::clone(&world.player),
+            Rc::clone(textures),
+            animations.to_vec(),
+            Rc::clone(&world.game_map),
+        );
+        raycaster.set_texture_filter(settings.texture_filter);
+        raycaster
+    }
+
+    // Size the view is rendered at this run
+    fn buffer_size(&self) -> (i32, i32) {
+        self.resolution.unwrap_or((self.settings.buffer_width, self.settings.buffer_height))
+    }
+
+    fn start_level(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+        let level_path = &self.levels[self.level_index];
+        self.world = Self::load_level(level_path, &self.weapons, &self.builtin_textures, &self.settings, self.difficulty, self.seed);
+        self.raycaster = Self::create_raycaster(rl, thread, &self.settings, self.buffer_size(), &self.textures, &self.animations, &self.world);
+        self.automap = Automap::default();
+        self.level_ticks = 0;
+        self.begin_recording();
+        self.set_state(rl, GameState::Playing);
+    }
+
+    // Only the first level played is recorded
+    fn begin_recording(&mut self) {
+        if let Some(path) = self.record_path.take() {
+            let demo = Demo::new(&self.levels[self.level_index], self.difficulty, self.seed, self.settings.fov);
+            self.recording = Some((path, demo));
+        }
+    }
+
+    pub fn finish_recording(&mut self) {
+        let Some((path, mut demo)) = self.recording.take() else {
+            return;
+        };
+        demo.end_hash = Some(self.world.state_hash());
+        match demo.save(&path) {
+            Ok(()) => println!("Recorded {} ticks to {}", demo.ticks.len(), path),
+            Err(error) => eprintln!("Failed to save demo to {}: {}", path, error),
+        }
+    }
+
+    // Saving or loading mid-recording would make the demo impossible to replay
+    fn saves_allowed(&self) -> bool {
+        self.recording.is_none()
+    }
+
+    fn start_demo(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread, demo: Demo, attract: bool) {
+        self.world = demo.load_world(&self.weapons, &self.builtin_textures);
+        self.raycaster = Self::create_raycaster(rl, thread, &self.settings, self.buffer_size(), &self.textures, &self.animations, &self.world);
+        self.automap = Automap::default();
+        self.level_ticks = 0;
+        self.playback = Some(Playback { demo, tick: 0, attract });
+        self.set_state(rl, GameState::Demo);
+    }
+
+    fn start_attract(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+        self.idle_frames = 0;
+        let path = ATTRACT_DEMOS[self.attract_index % ATTRACT_DEMOS.len()];
+        self.attract_index += 1;
+
+        match Demo::load(path) {
+            Ok(demo) => self.start_demo(rl, thread, demo, true),
+            Err(error) => eprintln!("Can't play {}: {}", path, error),
+        }
+    }
+
+    fn update_demo(&mut self, rl: &mut RaylibHandle, input: &InputFrame) {
+        let Some(playback) = &mut self.playback else {
+            self.set_state(rl, GameState::Title);
+            return;
+        };
+
+        let skipped = input.is_pressed(Action::MenuBack)
+            || (playback.attract && (input.is_pressed(Action::MenuSelect) || input.is_pressed(Action::Fire)));
+        if !skipped {
+            let steps = if input.is_down(Action::MenuRight) { FAST_FORWARD_TICKS } else { 1 };
+            for _ in 0..steps {
+                let Some(tick) = playback.demo.ticks.get(playback.tick) else {
+                    break;
+                };
+                self.world.tick(tick);
+                playback.tick += 1;
+                self.level_ticks += 1;
+            }
+            if playback.tick < playback.demo.ticks.len() {
+                return;
+            }
+
+            let hash = self.world.state_hash();
+            match playback.demo.end_hash {
+                Some(expected) if expected != hash => {
+                    eprintln!("Demo desynced: expected end state {:016x}, got {:016x}", expected, hash)
+                }
+                _ => println!("Demo finished after {} ticks, end state {:016x}", playback.tick, hash),
+            }
+        }
+
+        self.playback = None;
+        self.set_state(rl, GameState::Title);
+    }
+
+    fn set_state(&mut self, rl: &mut RaylibHandle, state: GameState) {
+        self.state = state;
+        self.menu = Menu::default();
+        self.rebinding = None;
+        self.idle_frames = 0;
+
+        // A recording ends with its level, or when the player leaves it
+        let in_level = matches!(
+            state,
+            GameState::Playing | GameState::Paused | GameState::Options | GameState::SaveMenu | GameState::LoadMenu
+        );
+        if !in_level {
+            self.finish_recording();
+        }
+
+        // The mouse turns the player while playing and is free everywhere else
+        if state == GameState::Playing {
+            rl.disable_cursor();
+        } else {
+            rl.enable_cursor();
+        }
+    }
+
+    fn save_settings(&self) {
+        if let Err(error) = self.settings.save(&self.settings_path) {
+            eprintln!("Failed to save settings to {}: {}", self.settings_path.display(), error);
+        }
+    }
+
+    pub fn update(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+        self.frame_counter += 1;
+
+        if let Some(action) = self.rebinding {
+            self.update_rebinding(rl, action);
+            return;
+        }
+
+        let input = InputFrame::poll(rl, &self.settings);
+        let item_count = self.menu_items().1.len();
+
+        match self.state {
+            GameState::Title => {
+                if input.is_pressed(Action::MenuSelect) || input.is_pressed(Action::Fire) {
+                    self.set_state(rl, GameState::MainMenu);
+                } else {
+                    self.idle_frames += 1;
+                    if self.idle_frames >= ATTRACT_DELAY {
+                        self.start_attract(rl, thread);
+                    }
+                }
+            }
+            GameState::MainMenu => match self.menu.update(&input, item_count) {
+                MenuEvent::Select(0) => {
+                    self.level_index = 0;
+                    self.start_level(rl, thread);
+                }
+                MenuEvent::Select(1) => self.open_slots(rl, GameState::LoadMenu, GameState::MainMenu),
+                MenuEvent::Select(2) => self.open_options(rl, GameState::MainMenu),
+                MenuEvent::Select(3) => self.quit = true,
+                MenuEvent::Back => self.set_state(rl, GameState::Title),
+                _ => {}
+            },
+            GameState::Playing => self.update_playing(rl, thread, &input),
+            GameState::Paused => match self.menu.update(&input, item_count) {
+                MenuEvent::Select(0) | MenuEvent::Back => self.set_state(rl, GameState::Playing),
+                MenuEvent::Select(1) if self.saves_allowed() => self.open_slots(rl, GameState::SaveMenu, GameState::Paused),
+                MenuEvent::Select(2) if self.saves_allowed() => self.open_slots(rl, GameState::LoadMenu, GameState::Paused),
+                MenuEvent::Select(3) => self.open_options(rl, GameState::Paused),
+                MenuEvent::Select(4) => self.set_state(rl, GameState::MainMenu),
+                _ => {}
+            },
+            GameState::Options => self.update_options(rl, thread, &input, item_count),
+            GameState::SaveMenu | GameState::LoadMenu => self.update_slots(rl, thread, &input, item_count),
+            GameState::Demo => self.update_demo(rl, &input),
+            GameState::GameOver => match self.menu.update(&input, item_count) {
+                MenuEvent::Select(0) => self.start_level(rl, thread),
+                MenuEvent::Select(1) | MenuEvent::Back => self.set_state(rl, GameState::MainMenu),
+                _ => {}
+            },
+            GameState::Intermission => {
+                if input.is_pressed(Action::MenuSelect) || input.is_pressed(Action::Fire) {
+                    if self.level_index + 1 < self.levels.len() {
+                        self.level_index += 1;
+                        self.start_level(rl, thread);
+                    } else {
+                        self.set_state(rl, GameState::MainMenu);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_playing(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread, input: &InputFrame) {
+        if input.is_pressed(Action::Pause) {
+            self.set_state(rl, GameState::Paused);
+            return;
+        }
+        if input.is_pressed(Action::QuickSave) && self.saves_allowed() {
+            self.save_game(QUICKSAVE_SLOT);
+        }
+        if input.is_pressed(Action::QuickLoad) && self.saves_allowed() {
+            if let Err(error) = self.load_game(rl, thread, QUICKSAVE_SLOT) {
+                self.world.player.borrow_mut().show_message(format!("Can't quickload: {}", error));
+            }
+            return;
+        }
+
+        self.automap.update(input);
+        if let Some((_, demo)) = &mut self.recording {
+            demo.ticks.push(*input);
+        }
+        self.world.tick(input);
+        self.level_ticks += 1;
+
+        let (is_dead, level_complete) = {
+            let player = self.world.player.borrow();
+            (player.health <= 0, player.level_complete)
+        };
+        if is_dead {
+            self.set_state(rl, GameState::GameOver);
+        } else if level_complete {
+            self.set_state(rl, GameState::Intermission);
+        }
+    }
+
+    fn open_options(&mut self, rl: &mut RaylibHandle, from: GameState) {
+        self.menu_return = from;
+        self.set_state(rl, GameState::Options);
+    }
+
+    fn open_slots(&mut self, rl: &mut RaylibHandle, state: GameState, from: GameState) {
+        self.slots = (1..=SAVE_SLOTS).map(|slot| format!("slot{}", slot)).collect();
+        if state == GameState::LoadMenu {
+            self.slots.insert(0, QUICKSAVE_SLOT.to_string());
+        }
+        self.slot_labels = self.slots.iter().map(|slot| Self::slot_label(slot)).collect();
+        self.menu_return = from;
+        self.set_state(rl, state);
+    }
+
+    fn slot_label(slot: &str) -> String {
+        let path = save::slot_path(slot);
+        if !path.exists() {
+            return "Empty".to_string();
+        }
+        match SaveData::read(&path) {
+            Ok(data) => data.get("GAME", "description").unwrap_or("Saved game").to_string(),
+            Err(_) => "Unreadable".to_string(),
+        }
+    }
+
+    fn update_slots(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread, input: &InputFrame, item_count: usize) {
+        let back = item_count - 1;
+
+        match self.menu.update(input, item_count) {
+            MenuEvent::Back => self.set_state(rl, self.menu_return),
+            MenuEvent::Select(i) if i == back => self.set_state(rl, self.menu_return),
+            MenuEvent::Select(i) if self.state == GameState::SaveMenu => {
+                let slot = self.slots[i].clone();
+                self.save_game(&slot);
+                self.set_state(rl, GameState::Playing);
+            }
+            MenuEvent::Select(i) => {
+                let slot = self.slots[i].clone();
+                if let Err(error) = self.load_game(rl, thread, &slot) {
+                    eprintln!("Failed to load {}: {}", save::slot_path(&slot).display(), error);
+                    self.slot_labels[i] = error;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn save_game(&self, slot: &str) {
+        let level = &self.levels[self.level_index];
+        let name = Path::new(level).file_stem().map_or(level.clone(), |stem| stem.to_string_lossy().to_string());
+        let seconds = self.level_ticks / 60;
+
+        let mut data = SaveData::default();
+        data.section("GAME");
+        data.put("description", format!("{}  {}:{:02}", name, seconds / 60, seconds % 60));
+        data.put("level", level);
+        data.put("level_index", self.level_index);
+        data.put("difficulty", self.difficulty.name());
+        data.put("level_ticks", self.level_ticks);
+        self.world.save_state(&mut data);
+
+        let path = save::slot_path(slot);
+        let message = match data.write(&path) {
+            Ok(()) => "Game saved".to_string(),
+            Err(error) => {
+                eprintln!("Failed to save to {}: {}", path.display(), error);
+                format!("Failed to save: {}", error)
+            }
+        };
+        self.world.player.borrow_mut().show_message(message);
+    }
+
+    // The level is loaded from its file as usual and the save then overwrites everything that
+    // can change during play, so nothing is applied unless the whole save could be read
+    fn load_game(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread, slot: &str) -> Result<(), String> {
+        let path = save::slot_path(slot);
+        if !path.exists() {
+            return Err("no saved game".to_string());
+        }
+        let data = SaveData::read(&path)?;
+
+        let level = data.get("GAME", "level")?.to_string();
+        if !Path::new(&level).exists() {
+            return Err(format!("level {} not found", level));
+        }
+        let name = data.get("GAME", "difficulty")?;
+        let difficulty = Difficulty::from_name(name).ok_or(format!("unknown difficulty '{}'", name))?;
+        let level_index: usize = data.parse("GAME", "level_index")?;
+        let level_ticks: usize = data.parse("GAME", "level_ticks")?;
+
+        // The seed doesn't matter here, the random state is restored from the save
+        let world = Self::load_level(&level, &self.weapons, &self.builtin_textures, &self.settings, difficulty, self.seed);
+        world.load_state(&data)?;
+        world.player.borrow_mut().set_fov(self.settings.fov);
+
+        // A save from a level outside the current campaign continues with just that level
+        self.level_index = if self.levels.get(level_index) == Some(&level) {
+            level_index
+        } else if let Some(index) = self.levels.iter().position(|path| *path == level) {
+            index
+        } else {
+            self.levels = vec![level];
+            0
+        };
+        self.difficulty = difficulty;
+        self.level_ticks = level_ticks;
+        self.world = world;
+        self.raycaster = Self::create_raycaster(rl, thread, &self.settings, self.buffer_size(), &self.textures, &self.animations, &self.world);
+        self.automap = Automap::default();
+        self.set_state(rl, GameState::Playing);
+        Ok(())
+    }
+
+    fn update_options(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread, input: &InputFrame, item_count: usize) {
+        let back = item_count - 1;
+
+        match self.menu.update(input, item_count) {
+            MenuEvent::Back => self.set_state(rl, self.menu_return),
+            MenuEvent::Select(i) if i == back => self.set_state(rl, self.menu_return),
+            MenuEvent::Select(i) if i >= OPTION_VALUES => self.rebinding = Some(REBINDABLE[i - OPTION_VALUES]),
+            MenuEvent::Select(i) => self.adjust_option(rl, thread, i, 1),
+            MenuEvent::Adjust(i, step) if i < OPTION_VALUES => self.adjust_option(rl, thread, i, step),
+            _ => {}
+        }
+    }
+
+    fn adjust_option(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread, option: usize, step: i32) {
+        let current_size = self.buffer_size();
+        let settings = &mut self.settings;
+        let step = step as f32;
+
+        match option {
+            0 => settings.mouse_sensitivity = ((settings.mouse_sensitivity + step * 0.0005) * 10000.0).round() / 10000.0,
+            1 => settings.fov = ((settings.fov + step * 0.05) * 100.0).round() / 100.0,
+            2 => {
+                let index = RESOLUTIONS.iter().position(|&resolution| resolution == current_size).unwrap_or(2);
+                let index = (index as i32 + step as i32).clamp(0, RESOLUTIONS.len() as i32 - 1) as usize;
+                (settings.buffer_width, settings.buffer_height) = RESOLUTIONS[index];
+                // Picking a resolution in game replaces the one from the command line
+                self.resolution = None;
+            }
+            3 => settings.volume = ((settings.volume + step * 0.1) * 10.0).round() / 10.0,
+            4 => {
+                let index = TEXTURE_FILTERS.iter().position(|&filter| filter == settings.texture_filter).unwrap_or(0);
+                let index = (index as i32 + step as i32).clamp(0, TEXTURE_FILTERS.len() as i32 - 1) as usize;
+                settings.texture_filter = TEXTURE_FILTERS[index];
+            }
+            _ => return,
+        }
+        // Stepping past either end of a range just stops there
+        settings.validate();
+
+        match option {
+            1 => self.world.player.borrow_mut().set_fov(self.settings.fov),
+            2 => self.raycaster = Self::create_raycaster(rl, thread, &self.settings, self.buffer_size(), &self.textures, &self.animations, &self.world),
+            3 => {
+                if let Some(audio) = &self.audio {
+                    audio.set_master_volume(self.settings.volume);
+                }
+            }
+            4 => self.raycaster.set_texture_filter(self.settings.texture_filter),
+            _ => {}
+        }
+
+        self.save_settings();
+    }
+
+    // Escape cancels, any other key, mouse button or wheel movement becomes the new binding
+    fn update_rebinding(&mut self, rl: &mut RaylibHandle, action: Action) {
+        if rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+            self.rebinding = None;
+            return;
+        }
+
+        let wheel = rl.get_mouse_wheel_move();
+        let binding = if let Some(key) = rl.get_key_pressed() {
+            Some(Binding::Key(key))
+        } else if let Some(button) = MOUSE_BUTTONS.iter().find(|&&button| rl.is_mouse_button_pressed(button)) {
+            Some(Binding::Mouse(*button))
+        } else if wheel > 0.0 {
+            Some(Binding::WheelUp)
+        } else if wheel < 0.0 {
+            Some(Binding::WheelDown)
+        } else {
+            None
+        };
+
+        if let Some(binding) = binding {
+            self.settings.rebind(action, binding);
+            self.rebinding = None;
+            self.save_settings();
+        }
+    }
+
+    // Title and entries of the menu shown in the current state
+    fn menu_items(&self) -> (&'static str, Vec<MenuItem>) {
+        match self.state {
+            GameState::MainMenu => (
+                "Main Menu",
+                vec![MenuItem::new("New Game"), MenuItem::new("Load Game"), MenuItem::new("Options"), MenuItem::new("Quit")],
+            ),
+            GameState::Paused => (
+                "Paused",
+                vec![
+                    MenuItem::new("Resume"),
+                    MenuItem::new("Save Game"),
+                    MenuItem::new("Load Game"),
+                    MenuItem::new("Options"),
+                    MenuItem::new("Quit to Menu"),
+                ],
+            ),
+            GameState::GameOver => ("You Died", vec![MenuItem::new("Restart Level"), MenuItem::new("Main Menu")]),
+            GameState::Options => {
+                let settings = &self.settings;
+                let degrees = 2.0 * settings.fov.atan().to_degrees();
+                let (buffer_width, buffer_height) = self.buffer_size();
+                let mut items = vec![
+                    MenuItem::with_value("Mouse sensitivity", format!("< {:.1} >", settings.mouse_sensitivity * 1000.0)),
+                    MenuItem::with_value("Field of view", format!("< {:.0} deg >", degrees)),
+                    MenuItem::with_value("Resolution", format!("< {}x{} >", buffer_width, buffer_height)),
+                    MenuItem::with_value("Volume", format!("< {:.0}% >", settings.volume * 100.0)),
+                    MenuItem::with_value("Textures", format!("< {} >", settings.texture_filter.label())),
+                ];
+                for action in REBINDABLE {
+                    let value = if self.rebinding == Some(action) {
+                        "press a key...".to_string()
+                    } else {
+                        let labels: Vec<String> = settings.bindings_for(action).iter().map(|binding| binding.label()).collect();
+                        labels.join(", ")
+                    };
+                    items.push(MenuItem::with_value(action.label(), value));
+                }
+                items.push(MenuItem::new("Back"));
+                ("Options", items)
+            }
+            GameState::SaveMenu | GameState::LoadMenu => {
+                let mut items: Vec<MenuItem> = self
+                    .slots
+                    .iter()
+                    .zip(&self.slot_labels)
+                    .map(|(slot, label)| {
+                        let name = match slot.strip_prefix("slot") {
+                            Some(number) => format!("Slot {}", number),
+                            None => "Quicksave".to_string(),
+                        };
+                        MenuItem::with_value(&name, label.clone())
+                    })
+                    .collect();
+                items.push(MenuItem::new("Back"));
+                let title = if self.state == GameState::SaveMenu { "Save Game" } else { "Load Game" };
+                (title, items)
+            }
+            _ => ("", Vec::new()),
+        }
+    }
+
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle) {
+        let show_world = match self.state {
+            GameState::Title | GameState::MainMenu => false,
+            GameState::Options | GameState::SaveMenu | GameState::LoadMenu => self.menu_return == GameState::Paused,
+            _ => true,
+        };
+        if show_world && !self.automap.is_open {
+            self.raycaster.render_frame(self.level_ticks);
+        }
+
+        let (title, items) = self.menu_items();
+        let intermission = self.intermission_text();
+        let fps = d.get_fps();
+        let mut canvas = self.raycaster.canvas();
+        let (width, height) = (canvas.width, canvas.height);
+
+        if show_world {
+            let player = self.world.player.borrow();
+            if self.automap.is_open {
+                self.automap.draw(&mut canvas, &self.world.game_map.borrow(), &player);
+            } else {
+                self.hud.draw_view(&mut canvas, &player);
+            }
+            self.hud.draw(&mut canvas, &player);
+            if !matches!(self.state, GameState::Playing | GameState::Demo) {
+                canvas.shade(MENU_SHADE);
+            }
+        } else {
+            canvas.fill_rect(0, 0, width, height, BACKGROUND_COLOR);
+        }
+
+        match self.state {
+            GameState::Title => {
+                self.font.draw(&mut canvas, "Raycasting with Rust", width / 2, height / 3, 2, Color::WHITE, TextAlign::Center);
+                if (self.frame_counter / 30).is_multiple_of(2) {
+                    self.font.draw(&mut canvas, "Press Enter", width / 2, height * 2 / 3, 1, Color::LIGHTGRAY, TextAlign::Center);
+                }
+            }
+            GameState::Intermission => {
+                self.font.draw(&mut canvas, &intermission, width / 2, height / 4, 2, Color::WHITE, TextAlign::Center);
+                self.font.draw(&mut canvas, "Press Enter to continue", width / 2, height - 40, 1, Color::LIGHTGRAY, TextAlign::Center);
+            }
+            GameState::Demo => {
+                self.font.draw(&mut canvas, "Demo", 4, 4, 1, Color::LIGHTGRAY, TextAlign::Left);
+                let attract = self.playback.as_ref().is_some_and(|playback| playback.attract);
+                if attract && (self.frame_counter / 30).is_multiple_of(2) {
+                    self.font.draw(&mut canvas, "Press Enter", width / 2, height / 3, 1, Color::WHITE, TextAlign::Center);
+                }
+            }
+            GameState::Playing => {}
+            _ => self.menu.draw(&mut canvas, &self.font, title, &items),
+        }
+
+        self.font.draw(&mut canvas, &format!("{} FPS", fps), width - 4, 4, 1, Color::LIME, TextAlign::Right);
+        self.raycaster.present(d);
+    }
+
+    fn intermission_text(&self) -> String {
+        let map = self.world.game_map.borrow();
+        let (kills, enemies) = map.enemy_count();
+        let items_taken = map.pickups.iter().filter(|pickup| pickup.is_taken).count();
+        let seconds = self.level_ticks / 60;
+
+        format!(
+            "Level Complete\n\nKills  {}/{}\nItems  {}/{}\nTime  {}:{:02}",
+            kills,
+            enemies,
+            items_taken,
+            map.pickups.len(),
+            seconds / 60,
+            seconds % 60,
+        )
+    }
+}
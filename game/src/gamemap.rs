@@ -1,9 +1,36 @@
 use std::fs;
 use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
 
 use raylib::prelude::*;
 
-use crate::Player;
+use crate::inventory::{AmmoKind, Inventory, KeyColor};
+use crate::lighting::{self, Lightmap, PointLight};
+use crate::rng::GameRng;
+use crate::save::{self, SaveData};
+use crate::texture::BuiltinTextures;
+
+pub const TILE_DOOR: u8 = 5;
+pub const TILE_EXIT: u8 = 6; // Using it ends the level
+pub const TILE_GRATE: u8 = 7;
+pub const TILE_FENCE: u8 = 8;
+// A wall only along one edge of the cell, the rest of it is open
+pub const TILE_THIN_NORTH: u8 = 9;
+pub const TILE_THIN_EAST: u8 = 10;
+pub const TILE_THIN_SOUTH: u8 = 11;
+pub const TILE_THIN_WEST: u8 = 12;
+// A wall across the cell from its north-west to south-east corner, and the other way round
+pub const TILE_DIAGONAL: u8 = 13;
+pub const TILE_DIAGONAL_FLIPPED: u8 = 14;
+
+const THIN_WALL_RADIUS: f64 = 0.1; // How close anything can get to a thin or diagonal wall
+const EXPLORE_RAYS: usize = 160; // Rays cast across the view each tick to mark what the automap shows
+
+// Textures of cells without an entry in the floor or ceiling layer
+pub const DEFAULT_FLOOR_TEXTURE: usize = 1;
+pub const DEFAULT_CEILING_TEXTURE: usize = 6;
+pub const CEILING_SKY: u8 = 255; // In the ceiling layer, the cell is open to the sky
 
 #[derive(Clone)]
 pub struct Sprite {
@@ -14,26 +41,169 @@ pub struct Sprite {
     pub dir_x: f64, // Velocity in Y direction
     pub dir_y: f64, // Velocity in Y direction
     pub is_projectile: f64,
-    pub is_ui: f64,
     pub is_destroyed: f64,
     pub texture: i32,
+    pub is_hidden: bool, // Picked up items stay in the list so sprite indices remain valid
+    pub health: f64, // Only sprites with health left can be shot
+    pub damage: f64, // Damage dealt by a projectile on impact
+}
+
+#[derive(Clone)]
+pub struct Door {
+    pub x: usize,
+    pub y: usize,
+    pub key: Option<KeyColor>,
+    pub is_open: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PickupKind {
+    Key(KeyColor),
+    Ammo(AmmoKind, i32),
+}
+
+impl PickupKind {
+    pub fn from_id(id: i32) -> Option<Self> {
+        match id {
+            4 => Some(PickupKind::Ammo(AmmoKind::Bullets, 10)),
+            5 => Some(PickupKind::Ammo(AmmoKind::Shells, 4)),
+            _ => KeyColor::from_id(id).map(PickupKind::Key),
+        }
+    }
+
+    pub fn texture(&self, textures: &BuiltinTextures) -> usize {
+        match self {
+            PickupKind::Key(key) => key.texture(textures),
+            PickupKind::Ammo(kind, _) => kind.texture(textures),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Pickup {
+    pub x: f64,
+    pub y: f64,
+    pub kind: PickupKind,
+    pub sprite_index: usize,
+    pub is_taken: bool,
+}
+
+// Depth cueing for a level: colours fade towards `color` between `start` and `end` tiles away
+#[derive(Clone, Copy)]
+pub struct Fog {
+    pub color: Color,
+    pub start: f32,
+    pub end: f32,
+    pub side_shade: f32, // Brightness of walls hit on a Y side, 1.0 draws them like X sides
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog { color: Color::BLACK, start: 0.0, end: 0.0, side_shade: 1.0 }
+    }
+}
+
+impl Fog {
+    // How far towards the fog colour something `distance` tiles away is, 0 when there is no fog
+    pub fn amount(&self, distance: f32) -> f32 {
+        if self.end <= self.start {
+            return 0.0;
+        }
+        ((distance - self.start) / (self.end - self.start)).clamp(0.0, 1.0)
+    }
 }
 
 #[derive(Clone)]
 pub struct GameMap {
     pub size: usize,
     pub map_data: Vec<u8>,
+    pub floor_data: Vec<u8>, // Texture of the floor of each cell, empty if the level has no floor layer
+    pub ceiling_data: Vec<u8>, // Same for the ceiling
+    pub height_data: Vec<f32>, // Height of the wall in each cell, empty if every wall is one unit tall
     pub sprites: Vec<Sprite>,
+    pub doors: Vec<Door>,
+    pub pickups: Vec<Pickup>,
+    pub explored: Vec<bool>, // Cells the player has had in view, shown on the automap
+    pub rng: GameRng, // Every random gameplay roll is taken from here
+    pub fog: Fog,
+    pub ambient: f32, // Light everything gets without a light nearby, 1.0 is full brightness
+    pub sprite_lights: Vec<(i32, PointLight)>, // Sprites with these textures glow
+    pub static_lights: Vec<(f32, f32, PointLight)>, // Lights fixed in place, these can be baked
+    pub lightmap: Option<Rc<Lightmap>>,
+    pub textures: BuiltinTextures, // Sky, grate and fence textures the renderer draws
+}
+
+// Tiles with holes in their texture, solid to walk into but not to sight, shots or light
+pub fn is_see_through(tile: u8) -> bool {
+    tile == TILE_GRATE || tile == TILE_FENCE
+}
+
+// End points, inside the cell, of the wall a thin or diagonal tile draws
+pub fn segment(tile: u8) -> Option<((f64, f64), (f64, f64))> {
+    match tile {
+        TILE_THIN_NORTH => Some(((0.0, 0.0), (1.0, 0.0))),
+        TILE_THIN_EAST => Some(((1.0, 0.0), (1.0, 1.0))),
+        TILE_THIN_SOUTH => Some(((1.0, 1.0), (0.0, 1.0))),
+        TILE_THIN_WEST => Some(((0.0, 1.0), (0.0, 0.0))),
+        TILE_DIAGONAL => Some(((0.0, 0.0), (1.0, 1.0))),
+        TILE_DIAGONAL_FLIPPED => Some(((1.0, 0.0), (0.0, 1.0))),
+        _ => None,
+    }
+}
+
+// Where the ray from (x, y) along (dir_x, dir_y) crosses the segment from `a` to `b`, as the
+// distance in multiples of the direction and the fraction of the way from `a` to `b`
+pub fn intersect_segment(x: f64, y: f64, dir_x: f64, dir_y: f64, a: (f64, f64), b: (f64, f64)) -> Option<(f64, f64)> {
+    let (edge_x, edge_y) = (b.0 - a.0, b.1 - a.1);
+    let denom = dir_x * edge_y - dir_y * edge_x;
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+
+    let (w_x, w_y) = (a.0 - x, a.1 - y);
+    let t = (w_x * edge_y - w_y * edge_x) / denom;
+    let u = (w_x * dir_y - w_y * dir_x) / denom;
+    // A little slack at the ends, so no ray slips between two segments meeting at a corner
+    (t > 1e-9 && (-1e-4..=1.0 + 1e-4).contains(&u)).then_some((t, u.clamp(0.0, 1.0)))
+}
+
+// Rows of comma separated numbers, up to the next blank line or section
+fn parse_grid<'a, T: FromStr>(lines: impl Iterator<Item = &'a str>) -> Vec<T> {
+    let mut grid = Vec::new();
+    for line in lines {
+        if line.trim().is_empty() || line.starts_with('[') {
+            break;
+        }
+        grid.extend(line.split(',').filter_map(|n| n.trim().parse::<T>().ok()));
+    }
+    grid
+}
+
+fn parse_values(line: &str) -> Vec<f64> {
+    line.replace("{", "")
+        .replace("}", "")
+        .split(',')
+        .filter_map(|n| n.trim().parse::<f64>().ok())
+        .collect()
 }
 
 impl GameMap {
-    pub fn load_map(file_path: &str) -> Self {
+    pub fn load_map(file_path: &str, textures: &BuiltinTextures) -> Self {
         let content = fs::read_to_string(Path::new(file_path))
             .expect("Failed to read level file");
 
         let mut lines = content.lines();
         let mut map_data = Vec::new();
+        let mut floor_data = Vec::new();
+        let mut ceiling_data = Vec::new();
+        let mut height_data = Vec::new();
         let mut sprites = Vec::new();
+        let mut doors = Vec::new();
+        let mut pickup_data = Vec::new();
+        let mut fog = Fog::default();
+        let mut ambient = 1.0;
+        let mut sprite_lights = Vec::new();
+        let mut static_lights = Vec::new();
         let mut size = 0;
 
         while let Some(line) = lines.next() {
@@ -53,18 +223,22 @@ impl GameMap {
 
                     map_data.extend(row);
                 }
+            } else if line.starts_with("[FLOOR_DATA]") {
+                // Same layout as [MAP_DATA], a texture index for each cell
+                floor_data = parse_grid(lines.by_ref());
+            } else if line.starts_with("[CEILING_DATA]") {
+                // 255 leaves the cell open to the sky
+                ceiling_data = parse_grid(lines.by_ref());
+            } else if line.starts_with("[HEIGHT_DATA]") {
+                // Wall heights in tiles, 0.5 is a half wall and 2 a pillar twice as tall
+                height_data = parse_grid(lines.by_ref());
             } else if line.starts_with("[SPRITES_DATA]") {
                 for sprite_line in lines.by_ref() {
                     if sprite_line.trim().is_empty() || sprite_line.starts_with("[") {
                         break;
                     }
-                    
-                    let values: Vec<f64> = sprite_line
-                        .replace("{", "")
-                        .replace("}", "")
-                        .split(',')
-                        .filter_map(|n| n.trim().parse::<f64>().ok())
-                        .collect();
+
+                    let values = parse_values(sprite_line);
 
                     if values.len() == 10 {
                         sprites.push(Sprite {
@@ -75,53 +249,676 @@ impl GameMap {
                             dir_x: values[4], // Velocity in Y direction
                             dir_y: values[5], // Velocity in Y direction
                             is_projectile: values[6],
-                            is_ui: values[7],
+                            // values[7] used to flag the weapon sprite, which the HUD draws now
                             is_destroyed: values[8],
                             texture: values[9] as i32,
+                            is_hidden: false,
+                            health: 0.0,
+                            damage: 0.0,
+                        });
+                    }
+                }
+            } else if line.starts_with("[DOORS_DATA]") {
+                // {x, y, key} where key is 0 for an unlocked door
+                for door_line in lines.by_ref() {
+                    if door_line.trim().is_empty() || door_line.starts_with("[") {
+                        break;
+                    }
+
+                    let values = parse_values(door_line);
+
+                    if values.len() == 3 {
+                        doors.push(Door {
+                            x: values[0] as usize,
+                            y: values[1] as usize,
+                            key: KeyColor::from_id(values[2] as i32),
+                            is_open: false,
                         });
                     }
                 }
+            } else if line.starts_with("[PICKUPS_DATA]") {
+                // {x, y, kind} where kind 1-3 is the red, blue or yellow key, 4 bullets and 5 shells
+                for pickup_line in lines.by_ref() {
+                    if pickup_line.trim().is_empty() || pickup_line.starts_with("[") {
+                        break;
+                    }
+
+                    let values = parse_values(pickup_line);
+
+                    if values.len() == 3 {
+                        if let Some(kind) = PickupKind::from_id(values[2] as i32) {
+                            pickup_data.push((values[0], values[1], kind));
+                        }
+                    }
+                }
+            } else if line.starts_with("[FOG]") {
+                // key = value lines: color = r, g, b, start and end in tiles, side_shade
+                for fog_line in lines.by_ref() {
+                    if fog_line.trim().is_empty() || fog_line.starts_with("[") {
+                        break;
+                    }
+
+                    let Some((key, value)) = fog_line.split_once('=') else {
+                        continue;
+                    };
+                    let values = parse_values(value);
+                    match (key.trim(), values.as_slice()) {
+                        ("color", &[r, g, b]) => fog.color = Color::new(r as u8, g as u8, b as u8, 255),
+                        ("start", &[start]) => fog.start = start as f32,
+                        ("end", &[end]) => fog.end = end as f32,
+                        ("side_shade", &[shade]) => fog.side_shade = shade as f32,
+                        _ => {}
+                    }
+                }
+            } else if line.starts_with("[LIGHTS]") {
+                // ambient = brightness, sprite = texture, r, g, b, radius, optional flicker,
+                // light = x, y, r, g, b, radius, optional flicker
+                for light_line in lines.by_ref() {
+                    if light_line.trim().is_empty() || light_line.starts_with("[") {
+                        break;
+                    }
+
+                    let Some((key, value)) = light_line.split_once('=') else {
+                        continue;
+                    };
+                    let values = parse_values(value);
+                    match (key.trim(), values.as_slice()) {
+                        ("ambient", &[value]) => ambient = value as f32,
+                        ("sprite", [texture, light @ ..]) => {
+                            if let Some(light) = PointLight::from_values(light) {
+                                sprite_lights.push((*texture as i32, light));
+                            }
+                        }
+                        ("light", [x, y, light @ ..]) => {
+                            if let Some(light) = PointLight::from_values(light) {
+                                static_lights.push((*x as f32, *y as f32, light));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
             }
         }
 
         assert_eq!(map_data.len(), size * size, "Map data is not a perfect square!");
 
+        // Door tiles without an entry in [DOORS_DATA] are plain unlocked doors
+        for (i, &tile) in map_data.iter().enumerate() {
+            let (x, y) = (i % size, i / size);
+            if tile == TILE_DOOR && !doors.iter().any(|door| door.x == x && door.y == y) {
+                doors.push(Door { x, y, key: None, is_open: false });
+            }
+        }
+
+        let pickups = pickup_data
+            .into_iter()
+            .map(|(x, y, kind)| {
+                sprites.push(Sprite {
+                    x,
+                    y,
+                    vx: 0.0,
+                    vy: 0.0,
+                    dir_x: 0.0,
+                    dir_y: 0.0,
+                    is_projectile: 0.0,
+                    is_destroyed: 0.0,
+                    texture: kind.texture(textures) as i32,
+                    is_hidden: false,
+                    health: 0.0,
+                    damage: 0.0,
+                });
+                Pickup { x, y, kind, sprite_index: sprites.len() - 1, is_taken: false }
+            })
+            .collect();
+
         GameMap {
             size,
             map_data,
+            floor_data,
+            ceiling_data,
+            height_data,
             sprites,
+            doors,
+            pickups,
+            explored: vec![false; size * size],
+            rng: GameRng::new(0),
+            fog,
+            ambient,
+            sprite_lights,
+            static_lights,
+            lightmap: None,
+            textures: *textures,
         }
     }
-}
-pub fn draw_board(d: &mut RaylibDrawHandle, _player: &Player, _map: &GameMap) {
-    let tile_size = 20; 
-    let mut y_offset = 0; 
 
-    for row in 0.._map.size {
-        let mut x_offset = 0; 
+    // Uses the lightmap baked for the level, if there is one and it is up to date
+    pub fn load_lightmap(&mut self, level_path: &str) {
+        let path = lighting::lightmap_path(level_path);
+        if !path.exists() {
+            return;
+        }
+        match Lightmap::load(&path, self) {
+            Ok(lightmap) => self.lightmap = Some(Rc::new(lightmap)),
+            Err(error) => eprintln!("level warning: {}: {}", path.display(), error),
+        }
+    }
+
+    // Floor and ceiling texture of a cell, cells outside the map use the defaults
+    pub fn surface_textures(&self, x: i32, y: i32) -> (usize, usize) {
+        let index = if x < 0 || x >= self.size as i32 || y < 0 || y >= self.size as i32 {
+            usize::MAX
+        } else {
+            y as usize * self.size + x as usize
+        };
+        (
+            self.floor_data.get(index).map_or(DEFAULT_FLOOR_TEXTURE, |&texture| texture as usize),
+            self.ceiling_data.get(index).map_or(DEFAULT_CEILING_TEXTURE, |&texture| texture as usize),
+        )
+    }
+
+    pub fn wall_height(&self, x: i32, y: i32) -> f32 {
+        if x < 0 || x >= self.size as i32 || y < 0 || y >= self.size as i32 {
+            return 1.0;
+        }
+        self.height_data.get(y as usize * self.size + x as usize).copied().unwrap_or(1.0)
+    }
+
+    // Nothing can show above a wall this tall
+    pub fn tallest_wall(&self) -> f32 {
+        self.height_data.iter().fold(1.0, |tallest, &height| tallest.max(height))
+    }
+
+    pub fn sprite_light(&self, texture: i32) -> Option<PointLight> {
+        self.sprite_lights.iter().find(|(id, _)| *id == texture).map(|(_, light)| *light)
+    }
+
+    pub fn door_at(&self, x: usize, y: usize) -> Option<&Door> {
+        self.doors.iter().find(|door| door.x == x && door.y == y)
+    }
+
+    // Opens the door at (x, y), or returns the key the player is missing
+    pub fn try_open_door(&mut self, x: usize, y: usize, inventory: &Inventory) -> Result<(), KeyColor> {
+        let size = self.size;
+        let Some(door) = self.doors.iter_mut().find(|door| door.x == x && door.y == y) else {
+            return Ok(());
+        };
+
+        if let Some(key) = door.key {
+            if !inventory.has_key(key) {
+                return Err(key);
+            }
+        }
+
+        door.is_open = true;
+        self.map_data[y * size + x] = 0;
+        Ok(())
+    }
+
+    pub fn explore(&mut self, x: i32, y: i32) {
+        if x >= 0 && x < self.size as i32 && y >= 0 && y < self.size as i32 {
+            self.explored[y as usize * self.size + x as usize] = true;
+        }
+    }
 
-        for col in 0.._map.size {
-            let value = _map.map_data[row * _map.size + col];
+    // Marks every cell the player can see from (x, y), casting a fan of rays across the view
+    // between `dir - projection` and `dir + projection`
+    pub fn explore_view(&mut self, x: f64, y: f64, dir: (f64, f64), projection: (f64, f64)) {
+        let max_dist = self.size as f64 * std::f64::consts::SQRT_2;
+        let mut seen = Vec::new();
+        for ray in 0..EXPLORE_RAYS {
+            let xcam = 2.0 * ray as f64 / (EXPLORE_RAYS - 1) as f64 - 1.0;
+            let (dir_x, dir_y) = (dir.0 + projection.0 * xcam, dir.1 + projection.1 * xcam);
+            let length = (dir_x * dir_x + dir_y * dir_y).sqrt();
+            if length == 0.0 {
+                continue;
+            }
+            self.cast_ray_through(x, y, dir_x / length, dir_y / length, max_dist, |map_x, map_y| seen.push((map_x, map_y)));
+        }
+        for (map_x, map_y) in seen {
+            self.explore(map_x, map_y);
+        }
+    }
 
-            let symbol = match value {
-                1 => '#',  
-                0 => '.',  
-                2 => 'O',  
-                3 => 'X',  
-                4 => '@',  
-                _ => ' ',  
-            };
+    pub fn is_explored(&self, x: i32, y: i32) -> bool {
+        x >= 0 && x < self.size as i32 && y >= 0 && y < self.size as i32 && self.explored[y as usize * self.size + x as usize]
+    }
+
+    pub fn is_wall(&self, x: i32, y: i32) -> bool {
+        if x < 0 || x >= self.size as i32 || y < 0 || y >= self.size as i32 {
+            return true;
+        }
+        let tile = self.map_data[y as usize * self.size + x as usize];
+        tile != 0 && segment(tile).is_none()
+    }
 
-            let position_text = format!("{}", symbol);
-            d.draw_text(&position_text, x_offset, y_offset, 6, Color::RAYWHITE);
+    // Segment of the thin or diagonal wall in a cell, in map coordinates
+    pub fn wall_segment(&self, x: i32, y: i32) -> Option<((f64, f64), (f64, f64))> {
+        if x < 0 || x >= self.size as i32 || y < 0 || y >= self.size as i32 {
+            return None;
+        }
+        let ((ax, ay), (bx, by)) = segment(self.map_data[y as usize * self.size + x as usize])?;
+        let (x, y) = (x as f64, y as f64);
+        Some(((x + ax, y + ay), (x + bx, y + by)))
+    }
 
-            x_offset += tile_size; 
+    // Whether a point is inside a wall, or too close to a thin or diagonal one
+    pub fn is_solid_at(&self, x: f64, y: f64) -> bool {
+        let (cell_x, cell_y) = (x.floor() as i32, y.floor() as i32);
+        if self.is_wall(cell_x, cell_y) {
+            return true;
         }
 
-        y_offset += tile_size; 
+        // Thin walls on the edge of a cell also keep things back on the neighbour's side
+        for near_y in cell_y - 1..=cell_y + 1 {
+            for near_x in cell_x - 1..=cell_x + 1 {
+                let Some((a, b)) = self.wall_segment(near_x, near_y) else {
+                    continue;
+                };
+                let (edge_x, edge_y) = (b.0 - a.0, b.1 - a.1);
+                let along = (((x - a.0) * edge_x + (y - a.1) * edge_y) / (edge_x * edge_x + edge_y * edge_y)).clamp(0.0, 1.0);
+                let (closest_x, closest_y) = (a.0 + edge_x * along, a.1 + edge_y * along);
+                if (x - closest_x).powi(2) + (y - closest_y).powi(2) < THIN_WALL_RADIUS * THIN_WALL_RADIUS {
+                    return true;
+                }
+            }
+        }
+        false
     }
 
-    let player_x_offset = _player.pos.x as i32 * tile_size;
-    let player_y_offset = _player.pos.y as i32 * tile_size;
-    d.draw_text("P", player_x_offset, player_y_offset, 6, Color::RED); 
+    // Projectiles pass grates and fences like hitscan shots do, anything else solid stops them
+    pub fn blocks_shots(&self, x: f64, y: f64) -> bool {
+        let (cell_x, cell_y) = (x.floor() as i32, y.floor() as i32);
+        let in_map = cell_x >= 0 && cell_x < self.size as i32 && cell_y >= 0 && cell_y < self.size as i32;
+        if in_map && is_see_through(self.map_data[cell_y as usize * self.size + cell_x as usize]) {
+            return false;
+        }
+        self.is_solid_at(x, y)
+    }
+
+    pub fn blocks_sight(&self, x: i32, y: i32) -> bool {
+        if x < 0 || x >= self.size as i32 || y < 0 || y >= self.size as i32 {
+            return true;
+        }
+        let tile = self.map_data[y as usize * self.size + x as usize];
+        tile != 0 && !is_see_through(tile) && segment(tile).is_none()
+    }
+
+    // Walks the grid from (x, y) along (dir_x, dir_y) and returns the distance to the first wall
+    // that can't be seen through, or `max_dist` if nothing is hit before that. The direction
+    // must be normalized.
+    pub fn cast_ray(&self, x: f64, y: f64, dir_x: f64, dir_y: f64, max_dist: f64) -> f64 {
+        self.cast_ray_through(x, y, dir_x, dir_y, max_dist, |_, _| {})
+    }
+
+    // Like `cast_ray`, but also calls `visit` with every cell the ray reaches, including the
+    // one it stops in
+    fn cast_ray_through(&self, x: f64, y: f64, dir_x: f64, dir_y: f64, max_dist: f64, mut visit: impl FnMut(i32, i32)) -> f64 {
+        let mut map_x = x.floor() as i32;
+        let mut map_y = y.floor() as i32;
+
+        let delta_x = if dir_x.abs() < 1e-20 { 1e30 } else { 1.0 / dir_x.abs() };
+        let delta_y = if dir_y.abs() < 1e-20 { 1e30 } else { 1.0 / dir_y.abs() };
+
+        let mut side_x = if dir_x < 0.0 { (x - map_x as f64) * delta_x } else { (map_x as f64 + 1.0 - x) * delta_x };
+        let mut side_y = if dir_y < 0.0 { (y - map_y as f64) * delta_y } else { (map_y as f64 + 1.0 - y) * delta_y };
+
+        let step_x = if dir_x < 0.0 { -1 } else { 1 };
+        let step_y = if dir_y < 0.0 { -1 } else { 1 };
+
+        // Thin and diagonal walls stop the ray only where it actually crosses them
+        let crossing = |map_x: i32, map_y: i32| {
+            self.wall_segment(map_x, map_y)
+                .and_then(|(a, b)| intersect_segment(x, y, dir_x, dir_y, a, b))
+                .map(|(dist, _)| dist)
+                .filter(|&dist| dist < max_dist)
+        };
+        visit(map_x, map_y);
+        if let Some(dist) = crossing(map_x, map_y) {
+            return dist;
+        }
+
+        loop {
+            let dist;
+            if side_x < side_y {
+                dist = side_x;
+                side_x += delta_x;
+                map_x += step_x;
+            } else {
+                dist = side_y;
+                side_y += delta_y;
+                map_y += step_y;
+            }
+
+            if dist >= max_dist {
+                return max_dist;
+            }
+            visit(map_x, map_y);
+            if self.blocks_sight(map_x, map_y) {
+                return dist;
+            }
+            if let Some(dist) = crossing(map_x, map_y) {
+                return dist;
+            }
+        }
+    }
+
+    pub fn update_projectiles(&mut self) {
+        let size = self.size;
+        let rows = self.map_data.len() / size.max(1);
+
+        for i in 0..self.sprites.len() {
+            if self.sprites[i].is_projectile != 1.0 || self.sprites[i].is_destroyed != 0.0 {
+                continue;
+            }
+
+            let sprite = &mut self.sprites[i];
+            let dir_x = sprite.dir_x;
+            let dir_y = sprite.dir_y;
+
+            // Normalize direction
+            let length = (dir_x * dir_x + dir_y * dir_y).sqrt();
+            if length == 0.0 {
+                continue;
+            }
+
+            // Add noise to the direction vector to introduce more deviation
+            let noise_x = (self.rng.next_f32() - 0.5) * 0.2; // Adjust the multiplier for more/less noise
+            let noise_y = (self.rng.next_f32() - 0.5) * 0.2;
+
+            // Projectiles travel a tenth of a tile per frame
+            sprite.x += (dir_x / length + noise_x as f64) * 0.1;
+            sprite.y += (dir_y / length + noise_y as f64) * 0.1;
+
+            let (x, y, damage) = (sprite.x, sprite.y, sprite.damage);
+
+            // A projectile that leaves the map is gone, it would otherwise fly on forever
+            let in_map = x >= 0.0 && y >= 0.0 && (x as usize) < size && (y as usize) < rows;
+            if !in_map || self.blocks_shots(x, y) {
+                self.sprites[i].is_destroyed = 1.0;
+                continue;
+            }
+
+            let target = self.sprites.iter().position(|target| {
+                target.health > 0.0 && !target.is_hidden && (target.x - x).powi(2) + (target.y - y).powi(2) < 0.3 * 0.3
+            });
+
+            if let Some(target) = target {
+                self.sprites[target].health -= damage;
+                self.sprites[i].is_destroyed = 1.0;
+            }
+        }
+
+        self.sprites.retain(|sprite| sprite.is_destroyed == 0.0);
+    }
+
+    // Enemies killed and the level's total. Enemies are the sprites that can be shot, a killed
+    // one is hidden with no health left, the same as a taken pickup.
+    pub fn enemy_count(&self) -> (usize, usize) {
+        let (mut killed, mut total) = (0, 0);
+        for (index, sprite) in self.sprites.iter().enumerate() {
+            if sprite.is_projectile != 0.0 || self.pickups.iter().any(|pickup| pickup.sprite_index == index) {
+                continue;
+            }
+            if sprite.health > 0.0 {
+                total += 1;
+            } else if sprite.is_hidden {
+                killed += 1;
+                total += 1;
+            }
+        }
+        (killed, total)
+    }
+
+    pub fn take_pickup(&mut self, index: usize) -> PickupKind {
+        let pickup = &mut self.pickups[index];
+        pickup.is_taken = true;
+        self.sprites[pickup.sprite_index].is_hidden = true;
+        pickup.kind
+    }
+
+    // The level file still supplies doors and pickups, the save only records what has changed
+    pub fn save_state(&self, data: &mut SaveData) {
+        data.section("MAP");
+        data.put("size", self.size);
+        data.put("rng", self.rng.state);
+        data.put_list("tiles", &self.map_data);
+        let explored: String = self.explored.iter().map(|&cell| if cell { '1' } else { '0' }).collect();
+        data.put("explored", explored);
+        let doors: Vec<bool> = self.doors.iter().map(|door| door.is_open).collect();
+        data.put_list("doors_open", &doors);
+        let pickups: Vec<bool> = self.pickups.iter().map(|pickup| pickup.is_taken).collect();
+        data.put_list("pickups_taken", &pickups);
+
+        for sprite in &self.sprites {
+            data.put_list("sprite", &[
+                sprite.x,
+                sprite.y,
+                sprite.vx,
+                sprite.vy,
+                sprite.dir_x,
+                sprite.dir_y,
+                sprite.is_projectile,
+                sprite.is_destroyed,
+                sprite.texture as f64,
+                if sprite.is_hidden { 1.0 } else { 0.0 },
+                sprite.health,
+                sprite.damage,
+            ]);
+        }
+    }
+
+    pub fn load_state(&mut self, data: &SaveData) -> Result<(), String> {
+        if data.parse::<usize>("MAP", "size")? != self.size {
+            return Err("Save was made on a different version of the level".to_string());
+        }
+
+        let tiles: Vec<u8> = data.parse_list("MAP", "tiles")?;
+        let explored: Vec<bool> = data.get("MAP", "explored")?.chars().map(|cell| cell == '1').collect();
+        let doors: Vec<bool> = data.parse_list("MAP", "doors_open")?;
+        let pickups: Vec<bool> = data.parse_list("MAP", "pickups_taken")?;
+        if tiles.len() != self.map_data.len()
+            || explored.len() != self.explored.len()
+            || doors.len() != self.doors.len()
+            || pickups.len() != self.pickups.len()
+        {
+            return Err("Save was made on a different version of the level".to_string());
+        }
+
+        let mut sprites = Vec::new();
+        for line in data.get_all("MAP", "sprite") {
+            let values: Vec<f64> = save::parse_list(line)?;
+            if values.len() != 12 {
+                return Err(format!("Bad sprite '{}'", line));
+            }
+            sprites.push(Sprite {
+                x: values[0],
+                y: values[1],
+                vx: values[2],
+                vy: values[3],
+                dir_x: values[4],
+                dir_y: values[5],
+                is_projectile: values[6],
+                is_destroyed: values[7],
+                texture: values[8] as i32,
+                is_hidden: values[9] != 0.0,
+                health: values[10],
+                damage: values[11],
+            });
+        }
+
+        self.rng = GameRng::new(data.parse("MAP", "rng")?);
+        self.map_data = tiles;
+        self.explored = explored;
+        for (door, is_open) in self.doors.iter_mut().zip(doors) {
+            door.is_open = is_open;
+        }
+        for (pickup, is_taken) in self.pickups.iter_mut().zip(pickups) {
+            pickup.is_taken = is_taken;
+        }
+        self.sprites = sprites;
+        Ok(())
+    }
+
+    // Flood fills from the player start, opening locked doors only once their key has been
+    // reached, and reports every locked door whose key can never be collected.
+    pub fn validate(&self, start: Vector2) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut keys = Inventory::default();
+        let mut reachable = vec![false; self.size * self.size];
+
+        for (name, layer) in [("FLOOR_DATA", &self.floor_data), ("CEILING_DATA", &self.ceiling_data)] {
+            if !layer.is_empty() && layer.len() != self.size * self.size {
+                warnings.push(format!("[{}] has {} cells, the map has {}", name, layer.len(), self.size * self.size));
+            }
+        }
+        if !self.height_data.is_empty() && self.height_data.len() != self.size * self.size {
+            warnings.push(format!("[HEIGHT_DATA] has {} cells, the map has {}", self.height_data.len(), self.size * self.size));
+        }
+        let flat = |(index, &height): (usize, &f32)| height <= 0.0 && self.map_data.get(index).is_some_and(|&tile| tile != 0);
+        if let Some(index) = self.height_data.iter().enumerate().position(flat) {
+            warnings.push(format!("wall at ({}, {}) has no height", index % self.size, index / self.size));
+        }
+
+        for door in &self.doors {
+            if self.map_data.get(door.y * self.size + door.x) != Some(&TILE_DOOR) {
+                warnings.push(format!("door at ({}, {}) is not on a door tile", door.x, door.y));
+            }
+        }
+
+        loop {
+            reachable.iter_mut().for_each(|cell| *cell = false);
+            let mut stack = vec![(start.x as usize, start.y as usize)];
+
+            while let Some((x, y)) = stack.pop() {
+                if x >= self.size || y >= self.size || reachable[y * self.size + x] {
+                    continue;
+                }
+
+                let passable = match self.map_data[y * self.size + x] {
+                    0 => true,
+                    // Thin and diagonal walls leave part of their cell open, so the fill goes
+                    // through them
+                    tile if segment(tile).is_some() => true,
+                    TILE_DOOR => self.door_at(x, y).and_then(|door| door.key).is_none_or(|key| keys.has_key(key)),
+                    _ => false,
+                };
+                if !passable {
+                    continue;
+                }
+
+                reachable[y * self.size + x] = true;
+                stack.push((x + 1, y));
+                stack.push((x.wrapping_sub(1), y));
+                stack.push((x, y + 1));
+                stack.push((x, y.wrapping_sub(1)));
+            }
+
+            let mut found_key = false;
+            for pickup in &self.pickups {
+                let PickupKind::Key(key) = pickup.kind else {
+                    continue;
+                };
+                let cell = pickup.y as usize * self.size + pickup.x as usize;
+                if reachable.get(cell) == Some(&true) && !keys.has_key(key) {
+                    keys.add_key(key);
+                    found_key = true;
+                }
+            }
+
+            if !found_key {
+                break;
+            }
+        }
+
+        for door in &self.doors {
+            if let Some(key) = door.key {
+                if !keys.has_key(key) {
+                    warnings.push(format!(
+                        "door at ({}, {}) needs the {} key, which is not reachable from the player start",
+                        door.x, door.y, key.name()
+                    ));
+                }
+            }
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} is not {}", actual, expected);
+    }
+
+    fn level() -> GameMap {
+        GameMap::load_map("res/level_1.txt", &BuiltinTextures::default())
+    }
+
+    #[test]
+    fn segment_hit() {
+        let (t, u) = intersect_segment(0.0, 0.25, 2.0, 0.0, (1.0, 0.0), (1.0, 1.0)).unwrap();
+        assert_close(t, 0.5); // In multiples of the direction
+        assert_close(u, 0.25);
+
+        let (t, u) = intersect_segment(0.5, 0.0, 0.0, 1.0, (0.0, 0.0), (1.0, 1.0)).unwrap();
+        assert_close(t, 0.5);
+        assert_close(u, 0.5);
+    }
+
+    #[test]
+    fn segment_miss() {
+        // Pointing away, passing beside, and starting on the segment itself
+        assert_eq!(intersect_segment(0.0, 0.5, -1.0, 0.0, (1.0, 0.0), (1.0, 1.0)), None);
+        assert_eq!(intersect_segment(0.0, 1.5, 1.0, 0.0, (1.0, 0.0), (1.0, 1.0)), None);
+        assert_eq!(intersect_segment(1.0, 0.5, 1.0, 0.0, (1.0, 0.0), (1.0, 1.0)), None);
+    }
+
+    #[test]
+    fn segment_grazing() {
+        // Through an end point, and just outside it but within the corner slack
+        assert_eq!(intersect_segment(0.0, 0.0, 1.0, 0.0, (1.0, 0.0), (1.0, 1.0)), Some((1.0, 0.0)));
+        assert_eq!(intersect_segment(0.0, 1.00005, 1.0, 0.0, (1.0, 0.0), (1.0, 1.0)), Some((1.0, 1.0)));
+        assert_eq!(intersect_segment(0.0, 1.01, 1.0, 0.0, (1.0, 0.0), (1.0, 1.0)), None);
+    }
+
+    #[test]
+    fn segment_parallel() {
+        assert_eq!(intersect_segment(0.0, 0.0, 0.0, 1.0, (1.0, 0.0), (1.0, 1.0)), None);
+        assert_eq!(intersect_segment(1.0, -1.0, 0.0, 1.0, (1.0, 0.0), (1.0, 1.0)), None); // Along it
+    }
+
+    #[test]
+    fn cast_ray_hits() {
+        let map = level();
+        assert_close(map.cast_ray(1.5, 1.5, -1.0, 0.0, 20.0), 0.5); // Outer wall
+        assert_close(map.cast_ray(1.5, 1.5, 1.0, 0.0, 20.0), 8.0); // Diagonal across (9, 1)
+        assert_close(map.cast_ray(7.5, 5.5, 0.0, -1.0, 20.0), 2.5); // Thin wall along the top of (7, 3)
+        assert_close(map.cast_ray(7.5, 5.5, 0.0, 1.0, 20.0), 4.5); // Through the fence at (7, 7)
+    }
+
+    #[test]
+    fn cast_ray_misses() {
+        let map = level();
+        assert_close(map.cast_ray(1.5, 8.5, 1.0, 0.0, 4.0), 4.0);
+        // Below the thin wall at (7, 3), which only blocks its top edge
+        assert_close(map.cast_ray(5.5, 3.5, 1.0, 0.0, 20.0), 4.5);
+    }
+
+    #[test]
+    fn cast_ray_grazing() {
+        let map = level();
+        // Onto the end of the thin wall at (7, 3), and just beside it
+        assert_close(map.cast_ray(7.0, 5.5, 0.0, -1.0, 20.0), 2.5);
+        assert_close(map.cast_ray(6.999, 5.5, 0.0, -1.0, 20.0), 4.5);
+    }
+
+    #[test]
+    fn cast_ray_parallel() {
+        // Along the thin walls at y = 3, edge on, so they don't stop it
+        let map = level();
+        assert_close(map.cast_ray(5.5, 3.0, 1.0, 0.0, 20.0), 4.5);
+    }
 }
@@ -0,0 +1,240 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use raylib::prelude::*;
+
+use crate::canvas::Canvas;
+use crate::font::{Font, TextAlign};
+use crate::inventory::KeyColor;
+use crate::player::{Player, WeaponState};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Start,
+    Center,
+    End,
+}
+
+#[derive(Clone)]
+struct HudElement {
+    align: Align,
+    valign: Align,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    scale: i32,
+    color: Color,
+    label: String,
+}
+
+impl Default for HudElement {
+    fn default() -> Self {
+        HudElement {
+            align: Align::Start,
+            valign: Align::Start,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            scale: 1,
+            color: Color::WHITE,
+            label: String::new(),
+        }
+    }
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    let parts: Vec<u8> = value
+        .split(',')
+        .filter_map(|n| n.trim().parse::<u8>().ok())
+        .collect();
+
+    match parts[..] {
+        [r, g, b] => Some(Color::new(r, g, b, 255)),
+        [r, g, b, a] => Some(Color::new(r, g, b, a)),
+        _ => None,
+    }
+}
+
+fn parse_align(value: &str) -> Align {
+    match value {
+        "center" => Align::Center,
+        "right" | "bottom" => Align::End,
+        _ => Align::Start,
+    }
+}
+
+pub struct Hud {
+    elements: Vec<(String, HudElement)>,
+    textures: Vec<Rc<RefCell<Image>>>,
+    face_texture: usize,
+    font: Rc<Font>,
+}
+
+impl Hud {
+    pub fn load(file_path: &str, textures: Vec<Rc<RefCell<Image>>>, face_texture: usize, font: Rc<Font>) -> Self {
+        let content = fs::read_to_string(Path::new(file_path))
+            .expect("Failed to read HUD layout file");
+
+        let mut elements: Vec<(String, HudElement)> = Vec::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].to_string();
+                elements.push((name, HudElement::default()));
+                continue;
+            }
+
+            let (Some((_, element)), Some((key, value))) = (elements.last_mut(), line.split_once('=')) else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim() {
+                "align" => element.align = parse_align(value),
+                "valign" => element.valign = parse_align(value),
+                "x" => element.x = value.parse().unwrap_or(0),
+                "y" => element.y = value.parse().unwrap_or(0),
+                "width" => element.width = value.parse().unwrap_or(0),
+                "height" => element.height = value.parse().unwrap_or(0),
+                "scale" => element.scale = value.parse().unwrap_or(1),
+                "color" => element.color = parse_color(value).unwrap_or(Color::WHITE),
+                "label" => element.label = value.to_string(),
+                _ => eprintln!("Unknown HUD property '{}' in {}", key.trim(), file_path),
+            }
+        }
+
+        Hud { elements, textures, face_texture, font }
+    }
+
+    // Elements missing from the layout file are simply not drawn
+    fn element(&self, name: &str) -> Option<&HudElement> {
+        self.elements
+            .iter()
+            .find(|(element_name, _)| element_name == name)
+            .map(|(_, element)| element)
+    }
+
+    // Top left corner of an element of the given size
+    fn place(element: &HudElement, width: i32, height: i32, canvas: &Canvas) -> (i32, i32) {
+        let x = match element.align {
+            Align::Start => element.x,
+            Align::Center => (canvas.width - width) / 2 + element.x,
+            Align::End => canvas.width - width + element.x,
+        };
+        let y = match element.valign {
+            Align::Start => element.y,
+            Align::Center => (canvas.height - height) / 2 + element.y,
+            Align::End => canvas.height - height + element.y,
+        };
+        (x, y)
+    }
+
+    // The weapon and crosshair belong to the 3D view and are left out while the automap is up
+    pub fn draw_view(&self, canvas: &mut Canvas, player: &Player) {
+        if let Some(element) = self.element("WEAPON") {
+            let texture = &self.textures[player.weapon_texture() as usize];
+            let (x, y) = Self::place(element, element.width, element.height, canvas);
+            canvas.draw_image(texture, x, y, element.width, element.height);
+        }
+
+        if let Some(element) = self.element("CROSSHAIR") {
+            // Follows the horizon when looking up or down, that is where shots go
+            let (x, y) = Self::place(element, 1, 1, canvas);
+            let y = y + player.horizon_shift(canvas.height);
+            let (length, gap) = (element.width, element.height);
+            canvas.fill_rect(x - gap - length, y, length, 1, element.color);
+            canvas.fill_rect(x + gap + 1, y, length, 1, element.color);
+            canvas.fill_rect(x, y - gap - length, 1, length, element.color);
+            canvas.fill_rect(x, y + gap + 1, 1, length, element.color);
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, player: &Player) {
+        if let Some(element) = self.element("STATUS_BAR") {
+            let width = if element.width == 0 { canvas.width } else { element.width };
+            let (x, y) = Self::place(element, width, element.height, canvas);
+            canvas.fill_rect(x, y, width, element.height, element.color);
+        }
+
+        self.draw_value(canvas, "HEALTH", &player.health.to_string(), None);
+        self.draw_value(canvas, "ARMOR", &player.armor.to_string(), None);
+
+        let weapon = player.weapon();
+        let ammo = match weapon.ammo {
+            None => "--".to_string(),
+            Some(kind) if weapon.magazine > 0 => format!("{}/{}", player.loaded[player.current_weapon], player.inventory.ammo(kind)),
+            Some(kind) => player.inventory.ammo(kind).to_string(),
+        };
+        let ammo_label = match player.weapon_state {
+            WeaponState::Reloading => Some("RELOADING"),
+            WeaponState::DryFire => Some("*CLICK*"),
+            _ => None,
+        };
+        self.draw_value(canvas, "AMMO", &ammo, ammo_label);
+
+        if let Some(element) = self.element("KEYS") {
+            let keys = [KeyColor::Red, KeyColor::Blue, KeyColor::Yellow];
+            let height = keys.len() as i32 * (element.height + 2) - 2;
+            let (x, y) = Self::place(element, element.width, height, canvas);
+
+            for (i, key) in keys.iter().enumerate() {
+                let color = if player.inventory.has_key(*key) { key.color() } else { element.color };
+                canvas.fill_rect(x, y + i as i32 * (element.height + 2), element.width, element.height, color);
+            }
+        }
+
+        if let Some(element) = self.element("FACE") {
+            let texture = &self.textures[self.face_texture];
+            let (frame_width, frame_height) = {
+                let image = texture.borrow();
+                (image.height, image.height)
+            };
+            let frames = (texture.borrow().width / frame_width).max(1);
+
+            // The last frame is the dead face, the others get bloodier as health drops. A single
+            // frame image is shown whatever the health.
+            let frame = if player.health <= 0 {
+                frames - 1
+            } else {
+                ((100 - player.health.min(100)) * (frames - 1) / 100).min((frames - 2).max(0))
+            };
+
+            let (x, y) = Self::place(element, element.width, element.height, canvas);
+            canvas.draw_image_region(texture, frame * frame_width, 0, frame_width, frame_height, x, y, element.width, element.height);
+        }
+
+        if let (Some(element), Some(message)) = (self.element("MESSAGE"), player.message()) {
+            // Long messages wrap to stay inside the screen margins
+            let max_width = canvas.width - 2 * element.x.abs() - 16;
+            let lines = self.font.wrap(message, max_width, element.scale);
+            let width = lines.iter().map(|line| self.font.measure(line, element.scale)).max().unwrap_or(0);
+            let height = lines.len() as i32 * self.font.line_height(element.scale);
+            let (x, y) = Self::place(element, width, height, canvas);
+            self.font.draw(canvas, &lines.join("\n"), x + width / 2, y, element.scale, element.color, TextAlign::Center);
+        }
+    }
+
+    // A small label above a large number
+    fn draw_value(&self, canvas: &mut Canvas, name: &str, value: &str, label: Option<&str>) {
+        let Some(element) = self.element(name) else {
+            return;
+        };
+
+        let label = label.unwrap_or(&element.label);
+        let width = self.font.measure(label, 1).max(self.font.measure(value, element.scale));
+        let height = self.font.line_height(1) + self.font.line_height(element.scale);
+        let (x, y) = Self::place(element, width, height, canvas);
+
+        self.font.draw(canvas, label, x, y, 1, Color::LIGHTGRAY, TextAlign::Left);
+        self.font.draw(canvas, value, x, y + self.font.line_height(1), element.scale, element.color, TextAlign::Left);
+    }
+}
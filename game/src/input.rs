@@ -0,0 +1,291 @@
+use raylib::prelude::*;
+
+use crate::settings::Settings;
+
+const WEAPON_KEYS: [KeyboardKey; 9] = [
+    KeyboardKey::KEY_ONE,
+    KeyboardKey::KEY_TWO,
+    KeyboardKey::KEY_THREE,
+    KeyboardKey::KEY_FOUR,
+    KeyboardKey::KEY_FIVE,
+    KeyboardKey::KEY_SIX,
+    KeyboardKey::KEY_SEVEN,
+    KeyboardKey::KEY_EIGHT,
+    KeyboardKey::KEY_NINE,
+];
+
+pub const MOUSE_BUTTONS: [MouseButton; 3] = [
+    MouseButton::MOUSE_BUTTON_LEFT,
+    MouseButton::MOUSE_BUTTON_RIGHT,
+    MouseButton::MOUSE_BUTTON_MIDDLE,
+];
+
+// Everything the game reacts to goes through an action, so keys can be rebound and a tick's
+// input can be stored as plain data
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    StrafeLeft,
+    StrafeRight,
+    Fire,
+    Use,
+    Reload,
+    NextWeapon,
+    PrevWeapon,
+    Automap,
+    QuickSave,
+    QuickLoad,
+    Jump,
+    Crouch,
+    MapZoomIn,
+    MapZoomOut,
+    MapReset,
+    Pause,
+    MenuUp,
+    MenuDown,
+    MenuLeft,
+    MenuRight,
+    MenuSelect,
+    MenuBack,
+}
+
+pub const ACTIONS: [Action; 24] = [
+    Action::MoveForward,
+    Action::MoveBack,
+    Action::StrafeLeft,
+    Action::StrafeRight,
+    Action::Fire,
+    Action::Use,
+    Action::Reload,
+    Action::NextWeapon,
+    Action::PrevWeapon,
+    Action::Automap,
+    Action::QuickSave,
+    Action::QuickLoad,
+    Action::Jump,
+    Action::Crouch,
+    Action::MapZoomIn,
+    Action::MapZoomOut,
+    Action::MapReset,
+    Action::Pause,
+    Action::MenuUp,
+    Action::MenuDown,
+    Action::MenuLeft,
+    Action::MenuRight,
+    Action::MenuSelect,
+    Action::MenuBack,
+];
+
+// The options screen only offers gameplay actions, so the menus can never become unreachable
+pub const REBINDABLE: [Action; 14] = [
+    Action::MoveForward,
+    Action::MoveBack,
+    Action::StrafeLeft,
+    Action::StrafeRight,
+    Action::Fire,
+    Action::Use,
+    Action::Reload,
+    Action::NextWeapon,
+    Action::PrevWeapon,
+    Action::Automap,
+    Action::QuickSave,
+    Action::QuickLoad,
+    Action::Jump,
+    Action::Crouch,
+];
+
+impl Action {
+    // Key used in the settings file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveForward => "move_forward",
+            Action::MoveBack => "move_back",
+            Action::StrafeLeft => "strafe_left",
+            Action::StrafeRight => "strafe_right",
+            Action::Fire => "fire",
+            Action::Use => "use",
+            Action::Reload => "reload",
+            Action::NextWeapon => "next_weapon",
+            Action::PrevWeapon => "prev_weapon",
+            Action::Automap => "automap",
+            Action::QuickSave => "quick_save",
+            Action::QuickLoad => "quick_load",
+            Action::Jump => "jump",
+            Action::Crouch => "crouch",
+            Action::MapZoomIn => "map_zoom_in",
+            Action::MapZoomOut => "map_zoom_out",
+            Action::MapReset => "map_reset",
+            Action::Pause => "pause",
+            Action::MenuUp => "menu_up",
+            Action::MenuDown => "menu_down",
+            Action::MenuLeft => "menu_left",
+            Action::MenuRight => "menu_right",
+            Action::MenuSelect => "menu_select",
+            Action::MenuBack => "menu_back",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS.iter().copied().find(|action| action.name() == name)
+    }
+
+    // Shown on the options screen
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveForward => "Move forward",
+            Action::MoveBack => "Move back",
+            Action::StrafeLeft => "Strafe left",
+            Action::StrafeRight => "Strafe right",
+            Action::Fire => "Fire",
+            Action::Use => "Use",
+            Action::Reload => "Reload",
+            Action::NextWeapon => "Next weapon",
+            Action::PrevWeapon => "Previous weapon",
+            Action::Automap => "Automap",
+            Action::QuickSave => "Quicksave",
+            Action::QuickLoad => "Quickload",
+            Action::Jump => "Jump",
+            Action::Crouch => "Crouch",
+            _ => self.name(),
+        }
+    }
+
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Binding {
+    Key(KeyboardKey),
+    Mouse(MouseButton),
+    WheelUp,
+    WheelDown,
+}
+
+impl Binding {
+    // Written to the settings file, e.g. KEY_W or MOUSE_BUTTON_LEFT
+    pub fn name(self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Mouse(button) => format!("{:?}", button),
+            Binding::WheelUp => "WHEEL_UP".to_string(),
+            Binding::WheelDown => "WHEEL_DOWN".to_string(),
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "WHEEL_UP" => return Some(Binding::WheelUp),
+            "WHEEL_DOWN" => return Some(Binding::WheelDown),
+            _ => {}
+        }
+        if let Some(button) = MOUSE_BUTTONS.iter().find(|button| format!("{:?}", button) == name) {
+            return Some(Binding::Mouse(*button));
+        }
+        (0..400)
+            .filter_map(key_from_i32)
+            .find(|key| format!("{:?}", key) == name)
+            .map(Binding::Key)
+    }
+
+    // Shorter form for the options screen
+    pub fn label(self) -> String {
+        let name = self.name();
+        match self {
+            Binding::Key(_) => name.trim_start_matches("KEY_").replace('_', " "),
+            Binding::Mouse(_) => name.replace("BUTTON_", "").replace('_', " "),
+            _ => name.replace('_', " "),
+        }
+    }
+
+    fn is_down(self, rl: &RaylibHandle) -> bool {
+        match self {
+            Binding::Key(key) => rl.is_key_down(key),
+            Binding::Mouse(button) => rl.is_mouse_button_down(button),
+            Binding::WheelUp | Binding::WheelDown => self.is_pressed(rl),
+        }
+    }
+
+    fn is_pressed(self, rl: &RaylibHandle) -> bool {
+        match self {
+            Binding::Key(key) => rl.is_key_pressed(key),
+            Binding::Mouse(button) => rl.is_mouse_button_pressed(button),
+            Binding::WheelUp => rl.get_mouse_wheel_move() > 0.0,
+            Binding::WheelDown => rl.get_mouse_wheel_move() < 0.0,
+        }
+    }
+}
+
+pub fn default_bindings() -> Vec<(Action, Binding)> {
+    use KeyboardKey::*;
+
+    vec![
+        (Action::MoveForward, Binding::Key(KEY_W)),
+        (Action::MoveBack, Binding::Key(KEY_S)),
+        (Action::StrafeLeft, Binding::Key(KEY_A)),
+        (Action::StrafeRight, Binding::Key(KEY_D)),
+        (Action::Fire, Binding::Mouse(MouseButton::MOUSE_BUTTON_LEFT)),
+        (Action::Use, Binding::Key(KEY_E)),
+        (Action::Reload, Binding::Key(KEY_R)),
+        (Action::NextWeapon, Binding::WheelUp),
+        (Action::PrevWeapon, Binding::WheelDown),
+        (Action::Automap, Binding::Key(KEY_TAB)),
+        (Action::QuickSave, Binding::Key(KEY_F5)),
+        (Action::QuickLoad, Binding::Key(KEY_F9)),
+        (Action::Jump, Binding::Key(KEY_SPACE)),
+        (Action::Crouch, Binding::Key(KEY_LEFT_CONTROL)),
+        (Action::MapZoomIn, Binding::Key(KEY_EQUAL)),
+        (Action::MapZoomIn, Binding::Key(KEY_KP_ADD)),
+        (Action::MapZoomOut, Binding::Key(KEY_MINUS)),
+        (Action::MapZoomOut, Binding::Key(KEY_KP_SUBTRACT)),
+        (Action::MapReset, Binding::Key(KEY_BACKSPACE)),
+        (Action::Pause, Binding::Key(KEY_ESCAPE)),
+        (Action::MenuUp, Binding::Key(KEY_UP)),
+        (Action::MenuDown, Binding::Key(KEY_DOWN)),
+        (Action::MenuLeft, Binding::Key(KEY_LEFT)),
+        (Action::MenuRight, Binding::Key(KEY_RIGHT)),
+        (Action::MenuSelect, Binding::Key(KEY_ENTER)),
+        (Action::MenuSelect, Binding::Key(KEY_KP_ENTER)),
+        (Action::MenuBack, Binding::Key(KEY_ESCAPE)),
+    ]
+}
+
+// One tick of input, already translated from devices into actions
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct InputFrame {
+    pub held: u32, // Bit per action that is down this tick
+    pub pressed: u32, // Bit per action that went down this tick
+    pub turn: f32, // Radians to turn, already scaled by the mouse sensitivity
+    pub look: f32, // Radians to tilt the view up, scaled the same way
+    pub weapon_slot: Option<usize>, // Number key pressed this tick
+}
+
+impl InputFrame {
+    pub fn poll(rl: &RaylibHandle, settings: &Settings) -> Self {
+        let mut frame = InputFrame::default();
+
+        for &(action, binding) in &settings.bindings {
+            if binding.is_down(rl) {
+                frame.held |= action.bit();
+            }
+            if binding.is_pressed(rl) {
+                frame.pressed |= action.bit();
+            }
+        }
+
+        frame.turn = -rl.get_mouse_delta().x * settings.mouse_sensitivity;
+        frame.look = -rl.get_mouse_delta().y * settings.mouse_sensitivity;
+        frame.weapon_slot = WEAPON_KEYS.iter().position(|&key| rl.is_key_pressed(key)).map(|i| i + 1);
+        frame
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.held & action.bit() != 0
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.pressed & action.bit() != 0
+    }
+}
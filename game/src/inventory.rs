@@ -0,0 +1,166 @@
+use std::str::FromStr;
+
+use raylib::prelude::*;
+
+use crate::save::SaveData;
+use crate::texture::BuiltinTextures;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyColor {
+    Red,
+    Blue,
+    Yellow,
+}
+
+impl KeyColor {
+    // Level files refer to keys by number: 1 = red, 2 = blue, 3 = yellow
+    pub fn from_id(id: i32) -> Option<Self> {
+        match id {
+            1 => Some(KeyColor::Red),
+            2 => Some(KeyColor::Blue),
+            3 => Some(KeyColor::Yellow),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [KeyColor::Red, KeyColor::Blue, KeyColor::Yellow].into_iter().find(|key| key.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyColor::Red => "red",
+            KeyColor::Blue => "blue",
+            KeyColor::Yellow => "yellow",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            KeyColor::Red => Color::new(210, 30, 30, 255),
+            KeyColor::Blue => Color::new(40, 70, 220, 255),
+            KeyColor::Yellow => Color::new(230, 200, 30, 255),
+        }
+    }
+
+    pub fn texture(&self, textures: &BuiltinTextures) -> usize {
+        match self {
+            KeyColor::Red => textures.keys[0],
+            KeyColor::Blue => textures.keys[1],
+            KeyColor::Yellow => textures.keys[2],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AmmoKind {
+    Bullets,
+    Shells,
+}
+
+impl AmmoKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AmmoKind::Bullets => "bullets",
+            AmmoKind::Shells => "shells",
+        }
+    }
+
+    // How much of it the player can carry
+    pub fn max(&self) -> i32 {
+        match self {
+            AmmoKind::Bullets => 200,
+            AmmoKind::Shells => 50,
+        }
+    }
+
+    pub fn texture(&self, textures: &BuiltinTextures) -> usize {
+        match self {
+            AmmoKind::Bullets => textures.bullets,
+            AmmoKind::Shells => textures.shells,
+        }
+    }
+}
+
+impl FromStr for AmmoKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        [AmmoKind::Bullets, AmmoKind::Shells]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or(format!("Unknown ammo type '{}'", name))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Inventory {
+    keys: Vec<KeyColor>,
+    ammo: Vec<(AmmoKind, i32)>,
+}
+
+impl Inventory {
+    pub fn has_key(&self, key: KeyColor) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn add_key(&mut self, key: KeyColor) {
+        if !self.has_key(key) {
+            self.keys.push(key);
+        }
+    }
+
+    pub fn ammo(&self, kind: AmmoKind) -> i32 {
+        self.ammo
+            .iter()
+            .find(|(held, _)| *held == kind)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    // Returns how much was actually added, ammo beyond the carry limit is left behind
+    pub fn add_ammo(&mut self, kind: AmmoKind, amount: i32) -> i32 {
+        let count = self.ammo(kind);
+        let added = amount.min(kind.max() - count).max(0);
+        self.set_ammo(kind, count + added);
+        added
+    }
+
+    // Takes up to `amount` and returns how much was available
+    pub fn take_ammo(&mut self, kind: AmmoKind, amount: i32) -> i32 {
+        let count = self.ammo(kind);
+        let taken = amount.min(count);
+        self.set_ammo(kind, count - taken);
+        taken
+    }
+
+    pub fn save_state(&self, data: &mut SaveData) {
+        data.section("INVENTORY");
+        let keys: Vec<&str> = self.keys.iter().map(|key| key.name()).collect();
+        data.put_list("keys", &keys);
+        for (kind, count) in &self.ammo {
+            data.put("ammo", format!("{}, {}", kind.name(), count));
+        }
+    }
+
+    pub fn load_state(data: &SaveData) -> Result<Self, String> {
+        let mut inventory = Inventory::default();
+        for name in data.parse_list::<String>("INVENTORY", "keys")? {
+            inventory.add_key(KeyColor::from_name(&name).ok_or(format!("Unknown key '{}'", name))?);
+        }
+        for line in data.get_all("INVENTORY", "ammo") {
+            let count = line.split_once(',').and_then(|(kind, count)| Some((kind.trim(), count.trim().parse().ok()?)));
+            let Some((kind, count)) = count else {
+                return Err(format!("Bad ammo '{}'", line));
+            };
+            inventory.set_ammo(kind.parse()?, count);
+        }
+        Ok(inventory)
+    }
+
+    fn set_ammo(&mut self, kind: AmmoKind, count: i32) {
+        match self.ammo.iter_mut().find(|(held, _)| *held == kind) {
+            Some(entry) => entry.1 = count,
+            None => self.ammo.push((kind, count)),
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use raylib::prelude::*;
+
+use crate::gamemap::GameMap;
+use crate::save;
+
+const SAMPLES_PER_TILE: usize = 4; // Light is stored at this many points along each side of a tile
+const FLICKER_FRAMES: usize = 4; // World ticks a flickering light keeps the same brightness
+const MAX_LIGHT: f32 = 2.0; // Overlapping lights can brighten a texture up to this much
+
+// Walls are lit from just in front of their face, half a sample back along the normal,
+// so light from the far side of the wall never bleeds through
+const FACE_OFFSET: f32 = 0.5 / SAMPLES_PER_TILE as f32;
+
+const LIGHTMAP_VERSION: u32 = 1;
+const LIGHTMAP_HEADER: &str = "RAYCASTER_LIGHTMAP";
+const LIGHT_SIZE: f32 = 0.25; // Radius of a baked light in tiles, bigger lights cast softer shadows
+const SHADOW_RAYS: usize = 32; // Points on a baked light that are checked for a clear line
+
+// Faces of a wall tile, in the order the lightmap stores them
+const FACE_WEST: usize = 0;
+const FACE_EAST: usize = 1;
+const FACE_NORTH: usize = 2;
+const FACE_SOUTH: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PointLight {
+    pub color: Color,
+    pub radius: f32, // In tiles, the light fades out completely at this distance
+    pub flicker: f32, // 0 for a steady light, up to 1 for one that can drop out entirely
+}
+
+impl PointLight {
+    // r, g, b, radius and an optional flicker, as written in the level and weapon files
+    pub fn from_values(values: &[f64]) -> Option<Self> {
+        let flicker = match values.len() {
+            4 => 0.0,
+            5 => values[4],
+            _ => return None,
+        };
+        Some(PointLight {
+            color: Color::new(values[0] as u8, values[1] as u8, values[2] as u8, 255),
+            radius: values[3] as f32,
+            flicker: flicker.clamp(0.0, 1.0) as f32,
+        })
+    }
+}
+
+// Cheap hash so every light flickers differently, but the same way on every run
+fn flicker_noise(time: usize, index: usize) -> f32 {
+    let mut n = (time as u32).wrapping_mul(0x9E37_79B1) ^ (index as u32).wrapping_mul(0x85EB_CA6B);
+    n ^= n >> 15;
+    n = n.wrapping_mul(0x2C1B_3C6D);
+    n ^= n >> 12;
+    (n & 0xFFFF) as f32 / 65536.0
+}
+
+pub fn apply(color: Color, light: [f32; 3]) -> Color {
+    let scale = |channel: u8, light: f32| (channel as f32 * light).min(255.0) as u8;
+    Color::new(scale(color.r, light[0]), scale(color.g, light[1]), scale(color.b, light[2]), color.a)
+}
+
+// Points spread evenly over the disc of a baked light
+fn disc_points() -> Vec<(f32, f32)> {
+    (0..SHADOW_RAYS)
+        .map(|i| {
+            let radius = LIGHT_SIZE * ((i as f32 + 0.5) / SHADOW_RAYS as f32).sqrt();
+            let angle = i as f32 * 2.399_963; // Golden angle
+            (radius * angle.cos(), radius * angle.sin())
+        })
+        .collect()
+}
+
+// How much of a light reaches (x, y): the falloff with distance, times the share of `points` on
+// the light that have a clear line to it. A single point gives hard shadows, many give soft ones.
+fn reach(map: &GameMap, &(light_x, light_y, light): &(f32, f32, PointLight), x: f32, y: f32, points: &[(f32, f32)]) -> f32 {
+    let distance = ((x - light_x).powi(2) + (y - light_y).powi(2)).sqrt();
+    if distance >= light.radius {
+        return 0.0;
+    }
+
+    let (mut tried, mut visible) = (0, 0);
+    for &(offset_x, offset_y) in points {
+        let (from_x, from_y) = (light_x + offset_x, light_y + offset_y);
+        if map.blocks_sight(from_x as i32, from_y as i32) {
+            continue;
+        }
+        tried += 1;
+
+        let (dx, dy) = (x - from_x, y - from_y);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length <= 1e-3
+            || map.cast_ray(from_x as f64, from_y as f64, (dx / length) as f64, (dy / length) as f64, length as f64) >= length as f64
+        {
+            visible += 1;
+        }
+    }
+    if tried == 0 {
+        return 0.0;
+    }
+
+    (1.0 - distance / light.radius).powi(2) * visible as f32 / tried as f32
+}
+
+fn add(value: &mut [f32; 3], light: &PointLight, amount: f32) {
+    let color = [light.color.r, light.color.g, light.color.b];
+    for channel in 0..3 {
+        value[channel] = (value[channel] + color[channel] as f32 / 255.0 * amount).min(MAX_LIGHT);
+    }
+}
+
+// Adds a light to every open sample of a grid it can reach
+fn add_light(values: &mut [[f32; 3]], map: &GameMap, source: &(f32, f32, PointLight), strength: f32, points: &[(f32, f32)]) {
+    let (light_x, light_y, light) = *source;
+    let side = map.size * SAMPLES_PER_TILE;
+    let samples = SAMPLES_PER_TILE as f32;
+    let first = |center: f32| ((center - light.radius) * samples).floor().max(0.0) as usize;
+    let last = |center: f32| (((center + light.radius) * samples).ceil() as usize).min(side - 1);
+
+    for sample_y in first(light_y)..=last(light_y) {
+        for sample_x in first(light_x)..=last(light_x) {
+            let x = (sample_x as f32 + 0.5) / samples;
+            let y = (sample_y as f32 + 0.5) / samples;
+            if map.blocks_sight(x as i32, y as i32) {
+                continue;
+            }
+
+            let amount = reach(map, source, x, y, points);
+            if amount > 0.0 {
+                add(&mut values[sample_y * side + sample_x], &light, amount * strength);
+            }
+        }
+    }
+}
+
+// Bilinear between the four nearest samples of a grid
+fn bilinear(values: &[[f32; 3]], side: usize, x: f32, y: f32) -> [f32; 3] {
+    let samples = SAMPLES_PER_TILE as f32;
+    let max = (side - 1) as f32;
+    let fx = (x * samples - 0.5).clamp(0.0, max);
+    let fy = (y * samples - 0.5).clamp(0.0, max);
+    let (x0, y0) = (fx as usize, fy as usize);
+    let (x1, y1) = ((x0 + 1).min(side - 1), (y0 + 1).min(side - 1));
+    let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+
+    let at = |x: usize, y: usize| values[y * side + x];
+    let (a, b, c, d) = (at(x0, y0), at(x1, y0), at(x0, y1), at(x1, y1));
+    let mut light = [0.0; 3];
+    for channel in 0..3 {
+        let top = a[channel] + (b[channel] - a[channel]) * tx;
+        let bottom = c[channel] + (d[channel] - c[channel]) * tx;
+        light[channel] = top + (bottom - top) * ty;
+    }
+    light
+}
+
+// Brightness per colour channel across the level. The ambient light and static lights are
+// worked out once per level, or come from its lightmap when one has been baked; the lights of
+// the current frame are added on top every frame. A sample only receives a light if a grid
+// raycast from the light reaches it, so walls cast hard shadows at the resolution of the samples.
+pub struct LightField {
+    pub active: bool, // False when the level is fully lit and nothing glows, lighting is skipped
+    lit: bool, // The level has lighting of its own, even with no lights this frame
+    side: usize,
+    base: Vec<[f32; 3]>,
+    values: Vec<[f32; 3]>,
+    baked: Option<Rc<Lightmap>>,
+}
+
+impl LightField {
+    pub fn new(map: &GameMap) -> Self {
+        let side = map.size * SAMPLES_PER_TILE;
+        let base = match &map.lightmap {
+            Some(lightmap) => lightmap.floor.clone(),
+            None => {
+                let mut base = vec![[map.ambient; 3]; side * side];
+                for light in &map.static_lights {
+                    add_light(&mut base, map, light, 1.0, &[(0.0, 0.0)]);
+                }
+                base
+            }
+        };
+
+        LightField {
+            active: false,
+            lit: map.ambient != 1.0 || !map.static_lights.is_empty() || map.lightmap.is_some(),
+            side,
+            values: base.clone(),
+            base,
+            baked: map.lightmap.clone(),
+        }
+    }
+
+    pub fn build(&mut self, map: &GameMap, lights: &[(f32, f32, PointLight)], time: usize) {
+        self.active = self.lit || !lights.is_empty();
+        if !self.active {
+            return;
+        }
+
+        self.values.clone_from(&self.base);
+        for (i, light) in lights.iter().enumerate() {
+            let strength = 1.0 - light.2.flicker * flicker_noise(time / FLICKER_FRAMES, i);
+            add_light(&mut self.values, map, light, strength, &[(0.0, 0.0)]);
+        }
+    }
+
+    pub fn sample(&self, x: f32, y: f32) -> [f32; 3] {
+        bilinear(&self.values, self.side, x, y)
+    }
+
+    // Light on a wall where a ray hit it. `side` and `step` are the raycaster's: which grid
+    // lines the ray crossed last, and the direction it travelled in.
+    pub fn sample_wall(&self, hit: Vector2, side: i32, step: Vector2) -> [f32; 3] {
+        let (x, y) = if side == 0 { (hit.x - step.x * FACE_OFFSET, hit.y) } else { (hit.x, hit.y - step.y * FACE_OFFSET) };
+        let Some(baked) = &self.baked else {
+            return self.sample(x, y);
+        };
+
+        let (tile_x, tile_y, face, along) = if side == 0 {
+            ((hit.x + step.x * 0.5).floor(), hit.y.floor(), if step.x > 0.0 { FACE_WEST } else { FACE_EAST }, hit.y.fract())
+        } else {
+            (hit.x.floor(), (hit.y + step.y * 0.5).floor(), if step.y > 0.0 { FACE_NORTH } else { FACE_SOUTH }, hit.x.fract())
+        };
+        if tile_x < 0.0 || tile_y < 0.0 || tile_x as usize >= baked.size || tile_y as usize >= baked.size {
+            return self.sample(x, y);
+        }
+
+        // The baked face, plus whatever this frame's lights add in front of it
+        let mut light = baked.face(tile_x as usize, tile_y as usize, face, along);
+        let (total, base) = (self.sample(x, y), bilinear(&self.base, self.side, x, y));
+        for channel in 0..3 {
+            light[channel] = (light[channel] + total[channel] - base[channel]).clamp(0.0, MAX_LIGHT);
+        }
+        light
+    }
+}
+
+// Light from the static lights of a level, baked ahead of time with soft shadows by
+// `game bake` and stored next to the level file
+pub struct Lightmap {
+    pub size: usize,
+    pub source: u64, // Hash of what the lighting was baked from, see `source_hash`
+    pub ambient: f32,
+    pub floor: Vec<[f32; 3]>, // Laid out like the samples of a `LightField`
+    pub faces: Vec<[f32; 3]>, // SAMPLES_PER_TILE samples along each of the four faces of every tile
+}
+
+pub fn lightmap_path(level_path: &str) -> PathBuf {
+    Path::new(level_path).with_extension("light")
+}
+
+// Anything that changes the baked light changes this, so stale lightmaps are noticed
+pub fn source_hash(map: &GameMap) -> u64 {
+    let mut source = format!("{} {} {:?}", map.size, map.ambient, map.map_data);
+    for (x, y, light) in &map.static_lights {
+        source += &format!(" {} {} {:?}", x, y, light);
+    }
+    save::fnv1a(source.as_bytes())
+}
+
+// Position of a face sample, just in front of the face
+fn face_point(tile_x: usize, tile_y: usize, face: usize, sample: usize) -> (f32, f32) {
+    let (x, y) = (tile_x as f32, tile_y as f32);
+    let along = (sample as f32 + 0.5) / SAMPLES_PER_TILE as f32;
+    match face {
+        FACE_WEST => (x - FACE_OFFSET, y + along),
+        FACE_EAST => (x + 1.0 + FACE_OFFSET, y + along),
+        FACE_NORTH => (x + along, y - FACE_OFFSET),
+        _ => (x + along, y + 1.0 + FACE_OFFSET),
+    }
+}
+
+impl Lightmap {
+    pub fn bake(map: &GameMap) -> Self {
+        let side = map.size * SAMPLES_PER_TILE;
+        let points = disc_points();
+
+        let mut floor = vec![[map.ambient; 3]; side * side];
+        for light in &map.static_lights {
+            add_light(&mut floor, map, light, 1.0, &points);
+        }
+
+        let mut faces = vec![[map.ambient; 3]; map.size * map.size * 4 * SAMPLES_PER_TILE];
+        for tile_y in 0..map.size {
+            for tile_x in 0..map.size {
+                if !map.is_wall(tile_x as i32, tile_y as i32) {
+                    continue;
+                }
+                for face in 0..4 {
+                    for sample in 0..SAMPLES_PER_TILE {
+                        let (x, y) = face_point(tile_x, tile_y, face, sample);
+                        if map.blocks_sight(x.floor() as i32, y.floor() as i32) {
+                            continue; // Buried against another wall, never seen
+                        }
+
+                        let value = &mut faces[((tile_y * map.size + tile_x) * 4 + face) * SAMPLES_PER_TILE + sample];
+                        for light in &map.static_lights {
+                            add(value, &light.2, reach(map, light, x, y, &points));
+                        }
+                    }
+                }
+            }
+        }
+
+        Lightmap { size: map.size, source: source_hash(map), ambient: map.ambient, floor, faces }
+    }
+
+    fn face_samples(&self, tile_x: usize, tile_y: usize, face: usize) -> &[[f32; 3]] {
+        let start = ((tile_y * self.size + tile_x) * 4 + face) * SAMPLES_PER_TILE;
+        &self.faces[start..start + SAMPLES_PER_TILE]
+    }
+
+    // Linear between the samples of a face, `along` runs from 0 to 1 with x or y
+    pub fn face(&self, tile_x: usize, tile_y: usize, face: usize, along: f32) -> [f32; 3] {
+        let samples = self.face_samples(tile_x, tile_y, face);
+        let f = (along * SAMPLES_PER_TILE as f32 - 0.5).clamp(0.0, (SAMPLES_PER_TILE - 1) as f32);
+        let (a, b) = (samples[f as usize], samples[(f as usize + 1).min(SAMPLES_PER_TILE - 1)]);
+        let t = f.fract();
+        [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        // Light is stored in hundredths, so 100 is full brightness
+        let write = |values: &[[f32; 3]]| -> String {
+            let values: Vec<String> = values
+                .iter()
+                .flat_map(|value| value.iter().map(|channel| ((channel * 100.0).round() as u32).to_string()))
+                .collect();
+            values.join(", ")
+        };
+
+        let mut content = format!("{} {}\n", LIGHTMAP_HEADER, LIGHTMAP_VERSION);
+        content += "# Baked by `game bake`, delete it to light the level at runtime instead\n\n[LIGHTMAP]\n";
+        content += &format!("size = {}\n", self.size);
+        content += &format!("source = {:016x}\n", self.source);
+
+        // One line per row of samples, r, g, b for each sample
+        content += "\n[FLOOR]\n";
+        let side = self.size * SAMPLES_PER_TILE;
+        for row in self.floor.chunks(side) {
+            content += &format!("{}\n", write(row));
+        }
+
+        // x, y, face (0 west, 1 east, 2 north, 3 south), then r, g, b for each sample.
+        // Faces that are never seen are left out.
+        content += "\n[FACES]\n";
+        for tile_y in 0..self.size {
+            for tile_x in 0..self.size {
+                for face in 0..4 {
+                    let (x, y) = face_point(tile_x, tile_y, face, 0);
+                    if x < 0.0 || y < 0.0 || x as usize >= self.size || y as usize >= self.size {
+                        continue;
+                    }
+                    let samples = self.face_samples(tile_x, tile_y, face);
+                    if samples.iter().all(|sample| *sample == [self.ambient; 3]) {
+                        continue;
+                    }
+                    content += &format!("{}, {}, {}, {}\n", tile_x, tile_y, face, write(samples));
+                }
+            }
+        }
+
+        fs::write(path, content).map_err(|error| error.to_string())
+    }
+
+    // Fails if the lightmap was baked for another version of the level
+    pub fn load(path: &Path, map: &GameMap) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let mut lines = content.lines();
+
+        let version = lines
+            .next()
+            .and_then(|header| header.strip_prefix(LIGHTMAP_HEADER))
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or("Not a lightmap")?;
+        if version != LIGHTMAP_VERSION {
+            return Err(format!("Lightmap version {} is not supported (expected {})", version, LIGHTMAP_VERSION));
+        }
+
+        let side = map.size * SAMPLES_PER_TILE;
+        let mut lightmap = Lightmap {
+            size: map.size,
+            source: 0,
+            ambient: map.ambient,
+            floor: Vec::with_capacity(side * side),
+            faces: vec![[map.ambient; 3]; map.size * map.size * 4 * SAMPLES_PER_TILE],
+        };
+
+        let read = |line: &str| -> Result<Vec<[f32; 3]>, String> {
+            let values = save::parse_list::<u32>(line)?;
+            if values.len() % 3 != 0 {
+                return Err(format!("Bad line '{}'", line));
+            }
+            Ok(values.chunks(3).map(|rgb| [rgb[0] as f32 / 100.0, rgb[1] as f32 / 100.0, rgb[2] as f32 / 100.0]).collect())
+        };
+
+        let mut section = "";
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line;
+                continue;
+            }
+
+            match section {
+                "[LIGHTMAP]" => {
+                    let Some((key, value)) = line.split_once('=') else {
+                        return Err(format!("Bad line '{}'", line));
+                    };
+                    let value = value.trim();
+                    let bad_value = || format!("Bad value '{}' for '{}'", value, key.trim());
+                    match key.trim() {
+                        "size" if value.parse::<usize>().ok() != Some(map.size) => {
+                            return Err("Baked for a level of another size".to_string());
+                        }
+                        "source" => lightmap.source = u64::from_str_radix(value, 16).map_err(|_| bad_value())?,
+                        _ => {}
+                    }
+                }
+                "[FLOOR]" => {
+                    let row = read(line)?;
+                    if row.len() != side {
+                        return Err(format!("Floor rows need {} samples", side));
+                    }
+                    lightmap.floor.extend(row);
+                }
+                "[FACES]" => {
+                    let parts: Vec<&str> = line.splitn(4, ',').collect();
+                    let [x, y, face, rest] = parts[..] else {
+                        return Err(format!("Bad face '{}'", line));
+                    };
+                    let parse = |value: &str| value.trim().parse::<usize>().ok();
+                    let (Some(x), Some(y), Some(face)) = (parse(x), parse(y), parse(face)) else {
+                        return Err(format!("Bad face '{}'", line));
+                    };
+                    let samples = read(rest)?;
+                    if x >= map.size || y >= map.size || face >= 4 || samples.len() != SAMPLES_PER_TILE {
+                        return Err(format!("Bad face '{}'", line));
+                    }
+                    let start = ((y * map.size + x) * 4 + face) * SAMPLES_PER_TILE;
+                    lightmap.faces[start..start + SAMPLES_PER_TILE].copy_from_slice(&samples);
+                }
+                _ => {}
+            }
+        }
+
+        if lightmap.floor.len() != side * side {
+            return Err(format!("Floor needs {} rows", side));
+        }
+        if lightmap.source != source_hash(map) {
+            return Err("Baked for another version of the level, run `game bake` again".to_string());
+        }
+        Ok(lightmap)
+    }
+}
@@ -1,91 +1,147 @@
 use raylib::prelude::*;
 
+use std::env;
+use std::process;
 use std::rc::Rc;
 use std::cell::RefCell;
 mod player;
-use player::Player;
-
 mod enemy;
-use enemy::Enemy;
-
+mod inventory;
+mod weapon;
+mod automap;
+mod canvas;
+mod font;
+mod hud;
+mod input;
+mod lighting;
+mod menu;
+mod settings;
 mod gamemap;
-use gamemap::GameMap;
-use crate::gamemap::draw_board;
-
 mod raycaster;
-use raycaster::Raycaster;
-
-const BUFFER_WIDTH: i32 = 550;
-const BUFFER_HEIGHT: i32 = 350;
+mod rng;
+mod save;
+mod texture;
+
+mod bake;
+mod benchmark;
+mod cli;
+mod demo;
+mod game;
+use game::Game;
+use settings::Settings;
+use texture::Manifest;
+
+// Images in manifest order, and the manifest with the animations playing on them
+fn load_textures() -> (Vec<Rc<RefCell<Image>>>, Manifest) {
+    let manifest = match texture::load_manifest(texture::MANIFEST_PATH) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    let textures = manifest
+        .files
+        .iter()
+        .map(|path| {
+            let image = Image::load_image(path).expect("Failed to load texture");
+            if let Some(problem) = texture::check_image(&image) {
+                eprintln!("texture warning: {}: {}", path, problem);
+            }
+            Rc::new(RefCell::new(image))
+        })
+        .collect();
+    (textures, manifest)
+}
 
 fn main() {
-    let (mut rl, thread) = raylib::init()
-        .size(1024, 768)
-        .title("Raycasting with Rust")
-        .vsync()
-        .build();
+    let mut options = match cli::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
 
-    rl.disable_cursor();
+    let settings_path = settings::default_path();
+    let settings = Settings::load(&settings_path);
 
+    // Command line sizes only apply to this run and never reach the settings file. They are
+    // checked on a copy, and the game keeps its own resolution apart from the saved one.
+    let mut run_settings = settings.clone();
+    if let Some((width, height)) = options.window {
+        (run_settings.window_width, run_settings.window_height) = (width, height);
+    }
+    if let Some((width, height)) = options.resolution {
+        (run_settings.buffer_width, run_settings.buffer_height) = (width, height);
+    }
+    for warning in run_settings.validate() {
+        eprintln!("settings warning: {}", warning);
+    }
+    if options.resolution.is_some() {
+        options.resolution = Some((run_settings.buffer_width, run_settings.buffer_height));
+    }
 
-    let texture_files = [
-        "res/greystone.png",
-        "res/wood.png",
-        "res/mossy.png",
-        "res/purplestone.png",
-        "res/redbrick.png",
-        "res/colorstone.png",
-        "res/bluestone.png",
-        "res/eagle.png",
-        "res/barrel.png",
-        "res/pillar.png",
-        "res/greenlight.png",
-        "res/demon.png",
-        "res/bullet.png",
-        "res/enemy1.png",
-        "res/enemy2.png",
-        "res/enemy3.png",
-        "res/enemy4.png",
-        "res/shotgun1.png",
-        "res/shotgun2.png",
-        "res/shotgun3.png",
-        "res/shotgun4.png",
-        "res/shotgun5.png",
-        "res/shotgun6.png",
-        "res/shotgun7.png",
-        "res/shotgun8.png",
-    ];
+    if let Some(path) = &options.verify {
+        if let Err(error) = demo::verify(path) {
+            eprintln!("{}: {}", path, error);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let textures: Vec<Rc<RefCell<Image>>> = texture_files
-        .iter()
-        .map(|&path| Rc::new(RefCell::new(Image::load_image(path).expect("Failed to load texture"))))
-        .collect();
+    if options.bake {
+        if let Err(error) = bake::run(&game::level_list(&options)) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let game_map = Rc::new(RefCell::new(GameMap::load_map("res/level_1.txt")));
+    if let Some(frames) = options.benchmark {
+        let level = game::level_list(&options).remove(0);
+        let seed = options.seed.unwrap_or_else(rng::random_seed);
+        let (textures, manifest) = load_textures();
+        if let Err(error) = benchmark::run(&run_settings, textures, manifest, &level, options.difficulty, seed, frames) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let player = Rc::new(RefCell::new(Player::new(game_map.clone())));
-    let enemy = Rc::new(RefCell::new(Enemy::new(game_map.clone())));
-    let _framebuffer = rl
-    .load_render_texture(&thread, BUFFER_WIDTH as u32, BUFFER_HEIGHT as u32)
-    .unwrap();
+    let mut builder = raylib::init();
+    builder
+        .size(run_settings.window_width, run_settings.window_height)
+        .title("Raycasting with Rust");
+    if settings.vsync {
+        builder.vsync();
+    }
+    if options.fullscreen {
+        builder.fullscreen();
+    }
+    let (mut rl, thread) = builder.build();
 
-    let mut raycaster = Raycaster::new(
-        BUFFER_WIDTH,
-        BUFFER_HEIGHT,
-        _framebuffer,
-        Rc::clone(&player),
-        textures,
-        Rc::clone(&game_map),
-    );
+    // Escape opens the pause menu instead of closing the window
+    rl.set_exit_key(None);
 
-    while !rl.window_should_close() {
-        let mut d = rl.begin_drawing(&thread);
+    let (textures, manifest) = load_textures();
+    let mut game = match Game::new(&mut rl, &thread, settings, settings_path, textures, manifest, &options) {
+        Ok(game) => game,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
 
-        raycaster.render_all(&mut d);
-        player.borrow_mut().update(&mut d);
-        enemy.borrow_mut().update();
+    while !rl.window_should_close() && !game.quit {
+        game.update(&mut rl, &thread);
 
-        draw_board(&mut d, &player.borrow(), &game_map.borrow());
-        d.draw_fps(15, 0);
+        let mut d = rl.begin_drawing(&thread);
+        game.draw(&mut d);
     }
+    game.finish_recording();
 }
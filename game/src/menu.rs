@@ -0,0 +1,94 @@
+use raylib::prelude::*;
+
+use crate::canvas::Canvas;
+use crate::font::{Font, TextAlign};
+use crate::input::{Action, InputFrame};
+
+const TITLE_SCALE: i32 = 2;
+const ITEM_SPACING: i32 = 2; // Extra pixels between menu lines
+const TEXT_COLOR: Color = Color::new(200, 200, 200, 255);
+const SELECTED_COLOR: Color = Color::new(240, 200, 60, 255);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MenuEvent {
+    None,
+    Select(usize),
+    Adjust(usize, i32), // Left or right on an item, -1 or 1
+    Back,
+}
+
+pub struct MenuItem {
+    pub label: String,
+    pub value: String,
+}
+
+impl MenuItem {
+    pub fn new(label: &str) -> Self {
+        MenuItem { label: label.to_string(), value: String::new() }
+    }
+
+    pub fn with_value(label: &str, value: String) -> Self {
+        MenuItem { label: label.to_string(), value }
+    }
+}
+
+// A vertical list of items. Items with a value are drawn as two columns meeting in the
+// middle of the screen, the others are centred.
+#[derive(Default)]
+pub struct Menu {
+    pub selected: usize,
+}
+
+impl Menu {
+    pub fn update(&mut self, input: &InputFrame, item_count: usize) -> MenuEvent {
+        if item_count == 0 {
+            return MenuEvent::None;
+        }
+        self.selected = self.selected.min(item_count - 1);
+
+        if input.is_pressed(Action::MenuUp) {
+            self.selected = (self.selected + item_count - 1) % item_count;
+        }
+        if input.is_pressed(Action::MenuDown) {
+            self.selected = (self.selected + 1) % item_count;
+        }
+
+        if input.is_pressed(Action::MenuSelect) {
+            MenuEvent::Select(self.selected)
+        } else if input.is_pressed(Action::MenuLeft) {
+            MenuEvent::Adjust(self.selected, -1)
+        } else if input.is_pressed(Action::MenuRight) {
+            MenuEvent::Adjust(self.selected, 1)
+        } else if input.is_pressed(Action::MenuBack) {
+            MenuEvent::Back
+        } else {
+            MenuEvent::None
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, font: &Font, title: &str, items: &[MenuItem]) {
+        let title_height = font.line_height(TITLE_SCALE) + 8;
+        let line_height = font.line_height(1) + ITEM_SPACING;
+
+        // Lists taller than the screen scroll to keep the selection visible
+        let visible = ((canvas.height - title_height - 16) / line_height).max(1) as usize;
+        let first = self.selected.saturating_sub(visible - 1).min(items.len().saturating_sub(visible));
+        let shown = items.len().min(visible);
+
+        let top = (canvas.height - title_height - shown as i32 * line_height) / 2;
+        let centre = canvas.width / 2;
+        font.draw(canvas, title, centre, top, TITLE_SCALE, Color::WHITE, TextAlign::Center);
+
+        for (row, (index, item)) in items.iter().enumerate().skip(first).take(shown).enumerate() {
+            let y = top + title_height + row as i32 * line_height;
+            let color = if index == self.selected { SELECTED_COLOR } else { TEXT_COLOR };
+
+            if item.value.is_empty() {
+                font.draw(canvas, &item.label, centre, y, 1, color, TextAlign::Center);
+            } else {
+                font.draw(canvas, &item.label, centre - 6, y, 1, color, TextAlign::Right);
+                font.draw(canvas, &item.value, centre + 6, y, 1, color, TextAlign::Left);
+            }
+        }
+    }
+}
@@ -2,23 +2,82 @@ use raylib::prelude::*;
 use std::rc::Rc;
 use std::cell::RefCell;
 
-use crate::GameMap;
-use crate::gamemap::Sprite;
+use crate::gamemap::GameMap;
+use crate::gamemap::{PickupKind, Sprite, TILE_DOOR, TILE_EXIT};
+use crate::input::{Action, InputFrame};
+use crate::inventory::{AmmoKind, Inventory};
+use crate::lighting::PointLight;
+use crate::save::SaveData;
+use crate::weapon::WeaponDef;
+
+const MESSAGE_FRAMES: usize = 180;
+const DRY_FIRE_FRAMES: usize = 20;
+const FLASH_FRAMES: usize = 4;
+const MAX_PITCH: f32 = 0.4; // Radians, further than this the sheared view looks wrong
+
+// Heights are in wall heights above the floor, speeds in wall heights per tick
+const EYE_HEIGHT: f32 = 0.5;
+const CROUCH_HEIGHT: f32 = 0.3;
+const CROUCH_SPEED: f32 = 0.02; // How fast the eye moves between standing and crouching
+const CROUCH_MOVE_SCALE: f32 = 0.5;
+const JUMP_SPEED: f32 = 0.045;
+const GRAVITY: f32 = 0.004;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WeaponState {
+    Idle,
+    Firing,
+    Reloading,
+    DryFire, // Trigger pulled with nothing to shoot, plays the click before switching weapons
+}
+
+impl WeaponState {
+    pub fn name(self) -> &'static str {
+        match self {
+            WeaponState::Idle => "idle",
+            WeaponState::Firing => "firing",
+            WeaponState::Reloading => "reloading",
+            WeaponState::DryFire => "dry_fire",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [WeaponState::Idle, WeaponState::Firing, WeaponState::Reloading, WeaponState::DryFire]
+            .into_iter()
+            .find(|state| state.name() == name)
+    }
+}
 
 #[derive(Clone)]
 pub struct Player {
     pub pos: Vector2,
     pub dir: Vector2,
     pub projection: Vector2,
+    pub pitch: f32, // Radians the view is tilted up, it only changes what is drawn
+    pub eye_height: f32, // Standing or crouching, moves smoothly between the two
+    pub jump_height: f32, // Feet above the floor while in the air
+    vertical_speed: f32,
     movespeed: f32,
     _map: Rc<RefCell<GameMap>>,
-    is_shooting: bool,
-    sprite_index: usize,
-    frame_counter: usize
+    frame_counter: usize,
+    pub health: i32,
+    pub armor: i32,
+    pub weapons: Vec<WeaponDef>,
+    pub current_weapon: usize,
+    pub weapon_state: WeaponState,
+    weapon_frame: usize,
+    weapon_timer: usize, // Frames left of a reload or dry fire
+    weapon_cooldown: usize,
+    flash_timer: usize, // Frames the muzzle flash stays lit
+    pub loaded: Vec<i32>, // Rounds in each weapon's magazine
+    pub inventory: Inventory,
+    message: String,
+    message_timer: usize,
+    pub level_complete: bool, // Set when the player uses an exit tile
 }
 
 impl Player {
-    pub fn new(_map: Rc<RefCell<GameMap>>) -> Self {
+    pub fn new(_map: Rc<RefCell<GameMap>>, weapons: Vec<WeaponDef>) -> Self {
         let dir = {
             let v = Vector2::new(-1.0, 0.1);
             let len = (v.x * v.x + v.y * v.y).sqrt();
@@ -26,145 +85,503 @@ impl Player {
         };
 
 
-        let mut map = _map.borrow_mut();
+        let loaded = weapons.iter().map(|weapon| weapon.magazine).collect();
 
-        let shotgun_sprite = Sprite {
-            x: 2.0,
-            y: 2.0,
-            vx: 0.0,
-            vy: 0.0,
-            dir_x: dir.x as f64,
-            dir_y: dir.y as f64,
-            is_projectile: 0.0,
-            is_ui: 1.0,
-            is_destroyed: 0.0,
-            texture: 17,
-        };
+        let mut inventory = Inventory::default();
+        inventory.add_ammo(AmmoKind::Bullets, 40);
+        inventory.add_ammo(AmmoKind::Shells, 8);
 
-        let sprite_index = map.sprites.len();
-        map.sprites.push(shotgun_sprite);
-        
         let mut player = Player {
             pos: Vector2::new(2.0, 2.0),
             dir,
             projection: Vector2::new(0.0, 0.66),
+            pitch: 0.0,
+            eye_height: EYE_HEIGHT,
+            jump_height: 0.0,
+            vertical_speed: 0.0,
             movespeed: 3.0 * 0.016,
             _map: _map.clone(),
-            is_shooting: false,
-            sprite_index,
             frame_counter: 0,
+            health: 100,
+            armor: 0,
+            weapons,
+            current_weapon: 0,
+            weapon_state: WeaponState::Idle,
+            weapon_frame: 0,
+            weapon_timer: 0,
+            weapon_cooldown: 0,
+            flash_timer: 0,
+            loaded,
+            inventory,
+            message: String::new(),
+            message_timer: 0,
+            level_complete: false,
         };
-        
+
         player.rotate(180.0);
         player
     }
 
-    pub fn update(&mut self, _rl: &mut RaylibDrawHandle) {
-        self.frame_counter += 1;
+    pub fn weapon(&self) -> &WeaponDef {
+        &self.weapons[self.current_weapon]
+    }
 
-        let mut spawn_bullet = None; // Store bullet separately
+    // Pixels the horizon moves down from the middle of a view this tall. Shots still fly
+    // level, so they hit whatever is on the horizon.
+    pub fn horizon_shift(&self, view_height: i32) -> i32 {
+        (view_height as f32 * self.pitch.tan()) as i32
+    }
 
-        {
-            let mut map = self._map.borrow_mut();
-            if let Some(sprite) = map.sprites.get_mut(self.sprite_index) {
-                if self.is_shooting && self.frame_counter % 10 == 0 {
-                    sprite.texture += 1;
-
-                    if sprite.texture == 19 {
-                        spawn_bullet = Some(Sprite {
-                            x: self.pos.x as f64,
-                            y: self.pos.y as f64,
-                            vx: 0.0,
-                            vy: 0.0,
-                            dir_x: self.dir.x as f64,
-                            dir_y: self.dir.y as f64,
-                            is_projectile: 1.0,
-                            is_ui: 0.0,
-                            is_destroyed: 0.0,
-                            texture: 12,
-                        });
-                    }
+    // Height of the camera above the floor
+    pub fn view_height(&self) -> f32 {
+        self.eye_height + self.jump_height
+    }
 
-                    if sprite.texture > 18 + 6 {
-                        sprite.texture = 17;
-                        self.is_shooting = false;
-                    }
-                }
-            }
-        } // `_map` borrow is dropped here
+    fn update_height(&mut self, input: &InputFrame) {
+        let crouching = input.is_down(Action::Crouch);
+        let target = if crouching { CROUCH_HEIGHT } else { EYE_HEIGHT };
+        self.eye_height += (target - self.eye_height).clamp(-CROUCH_SPEED, CROUCH_SPEED);
 
-        // Now that `_map` is no longer borrowed, we can safely push the bullet
-        if let Some(bullet) = spawn_bullet {
-            let mut map = self._map.borrow_mut();
-            map.sprites.push(bullet);
+        let on_ground = self.jump_height == 0.0 && self.vertical_speed == 0.0;
+        if on_ground && !crouching && input.is_pressed(Action::Jump) {
+            self.vertical_speed = JUMP_SPEED;
+        }
+        if !on_ground || self.vertical_speed > 0.0 {
+            self.jump_height += self.vertical_speed;
+            self.vertical_speed -= GRAVITY;
+            if self.jump_height <= 0.0 {
+                self.jump_height = 0.0;
+                self.vertical_speed = 0.0;
+            }
         }
+    }
 
-        self.rotate(-_rl.get_mouse_delta().x * 0.003);
+    pub fn weapon_texture(&self) -> i32 {
+        self.weapon().frames[self.weapon_frame]
+    }
+
+    pub fn update(&mut self, input: &InputFrame) {
+        self.update_weapon();
+
+        self.rotate(input.turn);
+        self.pitch = (self.pitch + input.look).clamp(-MAX_PITCH, MAX_PITCH);
+        self.update_height(input);
 
-        if _rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
+        let trigger = if self.weapon().automatic {
+            input.is_down(Action::Fire)
+        } else {
+            input.is_pressed(Action::Fire)
+        };
+        if trigger {
             self.shoot();
         }
 
-        if _rl.is_key_down(KeyboardKey::KEY_W) {
-            let new_pos = Vector2::new(self.pos.x + self.dir.x * self.movespeed, self.pos.y + self.dir.y * self.movespeed);
+        if input.is_pressed(Action::Reload) {
+            self.reload();
+        }
+
+        if let Some(slot) = input.weapon_slot {
+            if let Some(index) = self.weapons.iter().position(|weapon| weapon.slot == slot) {
+                self.switch_weapon(index);
+            }
+        }
+
+        let count = self.weapons.len();
+        if input.is_pressed(Action::NextWeapon) {
+            self.switch_weapon((self.current_weapon + 1) % count);
+        }
+        if input.is_pressed(Action::PrevWeapon) {
+            self.switch_weapon((self.current_weapon + count - 1) % count);
+        }
+
+        if input.is_pressed(Action::Use) {
+            self.use_tile();
+        }
+
+        self.collect_pickups();
+
+        if self.message_timer > 0 {
+            self.message_timer -= 1;
+        }
+
+        // Crouching walks at half speed
+        let speed = if input.is_down(Action::Crouch) { self.movespeed * CROUCH_MOVE_SCALE } else { self.movespeed };
+        if input.is_down(Action::MoveForward) {
+            let new_pos = Vector2::new(self.pos.x + self.dir.x * speed, self.pos.y + self.dir.y * speed);
             if self.is_valid_move(new_pos) {
                 self.pos = new_pos;
             }
         }
-        if _rl.is_key_down(KeyboardKey::KEY_S) {
-            let new_pos = Vector2::new(self.pos.x - self.dir.x * self.movespeed, self.pos.y - self.dir.y * self.movespeed);
+        if input.is_down(Action::MoveBack) {
+            let new_pos = Vector2::new(self.pos.x - self.dir.x * speed, self.pos.y - self.dir.y * speed);
             if self.is_valid_move(new_pos) {
                 self.pos = new_pos;
             }
         }
-        if _rl.is_key_down(KeyboardKey::KEY_A) {
-            let new_pos = Vector2::new(self.pos.x - self.dir.y * self.movespeed, self.pos.y + self.dir.x * self.movespeed);
+        if input.is_down(Action::StrafeLeft) {
+            let new_pos = Vector2::new(self.pos.x - self.dir.y * speed, self.pos.y + self.dir.x * speed);
             if self.is_valid_move(new_pos) {
                 self.pos = new_pos;
             }
         }
-        if _rl.is_key_down(KeyboardKey::KEY_D) {
-            let new_pos = Vector2::new(self.pos.x + self.dir.y * self.movespeed, self.pos.y - self.dir.x * self.movespeed);
+        if input.is_down(Action::StrafeRight) {
+            let new_pos = Vector2::new(self.pos.x + self.dir.y * speed, self.pos.y - self.dir.x * speed);
             if self.is_valid_move(new_pos) {
                 self.pos = new_pos;
             }
         }
     }
 
+    // The camera plane keeps its direction and only changes length
+    pub fn set_fov(&mut self, fov: f32) {
+        let length = (self.projection.x * self.projection.x + self.projection.y * self.projection.y).sqrt();
+        if (length - fov).abs() < 1e-6 {
+            return; // Already there, rescaling would only add rounding error
+        }
+        self.projection = Vector2::new(self.projection.x * fov / length, self.projection.y * fov / length);
+    }
+
+    fn update_weapon(&mut self) {
+        if self.weapon_cooldown > 0 {
+            self.weapon_cooldown -= 1;
+        }
+        self.flash_timer = self.flash_timer.saturating_sub(1);
+
+        match self.weapon_state {
+            WeaponState::Idle => {}
+            WeaponState::Firing => {
+                self.frame_counter += 1;
+
+                if self.frame_counter >= self.weapon().frame_time {
+                    self.frame_counter = 0;
+                    self.weapon_frame += 1;
+
+                    if self.weapon_frame == self.weapon().fire_frame {
+                        self.fire();
+                    }
+
+                    if self.weapon_frame >= self.weapon().frames.len() {
+                        self.weapon_frame = 0;
+                        self.weapon_state = WeaponState::Idle;
+                        self.weapon_cooldown = self.weapon().cooldown;
+                    }
+                }
+            }
+            WeaponState::Reloading => {
+                self.weapon_timer = self.weapon_timer.saturating_sub(1);
+                if self.weapon_timer == 0 {
+                    let weapon = self.weapon();
+                    let missing = weapon.magazine - self.loaded[self.current_weapon];
+                    if let Some(kind) = weapon.ammo {
+                        self.loaded[self.current_weapon] += self.inventory.take_ammo(kind, missing);
+                    }
+                    self.weapon_state = WeaponState::Idle;
+                }
+            }
+            WeaponState::DryFire => {
+                self.weapon_timer = self.weapon_timer.saturating_sub(1);
+                if self.weapon_timer == 0 {
+                    self.weapon_state = WeaponState::Idle;
+                    if let Some(index) = self.best_weapon() {
+                        self.switch_weapon(index);
+                    }
+                }
+            }
+        }
+    }
+
+    // Rounds the weapon at `index` can fire right now, counting its magazine and reserve ammo
+    fn available_ammo(&self, index: usize) -> i32 {
+        let Some(kind) = self.weapons[index].ammo else {
+            return i32::MAX;
+        };
+        self.loaded[index] + self.inventory.ammo(kind)
+    }
+
+    // The highest slot that still has something to shoot, so melee weapons come last
+    fn best_weapon(&self) -> Option<usize> {
+        (0..self.weapons.len())
+            .rev()
+            .find(|&index| self.available_ammo(index) > 0)
+    }
+
+    fn switch_weapon(&mut self, index: usize) {
+        // Finish the current attack or reload before putting the weapon away
+        if matches!(self.weapon_state, WeaponState::Firing | WeaponState::Reloading) || index == self.current_weapon {
+            return;
+        }
+
+        self.current_weapon = index;
+        self.weapon_state = WeaponState::Idle;
+        self.weapon_frame = 0;
+        self.weapon_cooldown = 0;
+    }
+
+    fn reload(&mut self) {
+        let weapon = self.weapon();
+        let can_reload = weapon.magazine > 0
+            && self.loaded[self.current_weapon] < weapon.magazine
+            && weapon.ammo.is_some_and(|kind| self.inventory.ammo(kind) > 0);
+
+        if self.weapon_state == WeaponState::Idle && can_reload {
+            self.weapon_state = WeaponState::Reloading;
+            self.weapon_timer = self.weapon().reload_time;
+            self.weapon_frame = 0;
+        }
+    }
+
     fn shoot(&mut self) {
-        if self.is_shooting {
+        if self.weapon_state != WeaponState::Idle || self.weapon_cooldown > 0 {
             return; // Prevent shooting again until animation resets
         }
 
-        self.is_shooting = true;
+        let weapon = self.weapon();
+        if let Some(kind) = weapon.ammo {
+            if weapon.magazine > 0 {
+                if self.loaded[self.current_weapon] == 0 {
+                    if self.inventory.ammo(kind) > 0 {
+                        self.reload();
+                    } else {
+                        self.dry_fire();
+                    }
+                    return;
+                }
+                self.loaded[self.current_weapon] -= 1;
+            } else if self.inventory.take_ammo(kind, 1) == 0 {
+                self.dry_fire();
+                return;
+            }
+        }
+
+        self.weapon_state = WeaponState::Firing;
         self.frame_counter = 0; // Reset animation counter
+        self.weapon_frame = 0; // Start animation from the first frame
 
-        let mut map = self._map.borrow_mut();
-        if let Some(sprite) = map.sprites.get_mut(self.sprite_index) {
-            sprite.texture = 17; // Start animation from the first frame
+        if self.weapon().fire_frame == 0 {
+            self.fire();
+        }
+    }
+
+    fn dry_fire(&mut self) {
+        self.weapon_state = WeaponState::DryFire;
+        self.weapon_timer = DRY_FIRE_FRAMES;
+        let ammo = self.weapon().ammo.map_or("ammo", |kind| kind.name());
+        self.show_message(format!("*click* Out of {}", ammo));
+    }
+
+    fn fire(&mut self) {
+        let weapon = self.weapon().clone();
+        if weapon.flash.is_some() {
+            self.flash_timer = FLASH_FRAMES;
+        }
+
+        for _ in 0..weapon.pellets {
+            let roll = self._map.borrow_mut().rng.next_f64();
+            let angle = (roll * 2.0 - 1.0) * weapon.spread;
+            let (sin, cos) = angle.sin_cos();
+            let dir_x = self.dir.x as f64 * cos - self.dir.y as f64 * sin;
+            let dir_y = self.dir.x as f64 * sin + self.dir.y as f64 * cos;
+
+            if weapon.hitscan {
+                self.fire_hitscan(dir_x, dir_y, &weapon);
+            } else {
+                self._map.borrow_mut().sprites.push(Sprite {
+                    x: self.pos.x as f64,
+                    y: self.pos.y as f64,
+                    vx: 0.0,
+                    vy: 0.0,
+                    dir_x,
+                    dir_y,
+                    is_projectile: 1.0,
+                    is_destroyed: 0.0,
+                    texture: 12,
+                    is_hidden: false,
+                    health: 0.0,
+                    damage: weapon.damage,
+                });
+            }
         }
     }
 
-    fn spawn_bullet(&mut self) {
+    fn fire_hitscan(&mut self, dir_x: f64, dir_y: f64, weapon: &WeaponDef) {
         let mut map = self._map.borrow_mut();
+        let (x, y) = (self.pos.x as f64, self.pos.y as f64);
+        let wall_dist = map.cast_ray(x, y, dir_x, dir_y, weapon.range);
 
-        
+        // The closest shootable sprite whose centre passes within reach of the ray
+        let mut target = None;
+        let mut target_dist = wall_dist;
+        for (i, sprite) in map.sprites.iter().enumerate() {
+            if sprite.health <= 0.0 || sprite.is_hidden {
+                continue;
+            }
+
+            let (to_x, to_y) = (sprite.x - x, sprite.y - y);
+            let along = to_x * dir_x + to_y * dir_y;
+            let across = (to_x * dir_y - to_y * dir_x).abs();
+            if along > 0.0 && along < target_dist && across < 0.35 {
+                target = Some(i);
+                target_dist = along;
+            }
+        }
+
+        if let Some(i) = target {
+            map.sprites[i].health -= weapon.damage;
+        }
     }
-    fn is_valid_move(&self, new_pos: Vector2) -> bool {
-        let map = self._map.borrow();
-        // Check if the new position is within bounds
-        let map_x = new_pos.x.floor() as i32;
-        let map_y = new_pos.y.floor() as i32;
-
-        // Check if the new position is within the bounds of the map and on a '0' field
-        if map_x >= 0 && map_x < map.size as i32 && map_y >= 0 && map_y < map.size as i32 {
-            let map_value = map.map_data[map_y as usize * map.size + map_x as usize];
-            map_value == 0 // Only allow movement on '0' fields
+
+    pub fn save_state(&self, data: &mut SaveData) {
+        data.section("PLAYER");
+        data.put_list("pos", &[self.pos.x, self.pos.y]);
+        data.put_list("dir", &[self.dir.x, self.dir.y]);
+        data.put_list("projection", &[self.projection.x, self.projection.y]);
+        data.put("pitch", self.pitch);
+        data.put("movespeed", self.movespeed);
+        data.put("eye_height", self.eye_height);
+        data.put("jump_height", self.jump_height);
+        data.put("vertical_speed", self.vertical_speed);
+        data.put("frame_counter", self.frame_counter);
+        data.put("health", self.health);
+        data.put("armor", self.armor);
+        data.put("current_weapon", self.current_weapon);
+        data.put("weapon_state", self.weapon_state.name());
+        data.put("weapon_frame", self.weapon_frame);
+        data.put("weapon_timer", self.weapon_timer);
+        data.put("weapon_cooldown", self.weapon_cooldown);
+        data.put_list("loaded", &self.loaded);
+        data.put("message", &self.message);
+        data.put("message_timer", self.message_timer);
+        data.put("level_complete", self.level_complete);
+        self.inventory.save_state(data);
+    }
+
+    // Weapons come from weapons.txt, so a save only fits if the same number of weapons is defined
+    pub fn load_state(&mut self, data: &SaveData) -> Result<(), String> {
+        let current_weapon: usize = data.parse("PLAYER", "current_weapon")?;
+        let loaded: Vec<i32> = data.parse_list("PLAYER", "loaded")?;
+        if current_weapon >= self.weapons.len() || loaded.len() != self.weapons.len() {
+            return Err("Save doesn't match the weapon definitions".to_string());
+        }
+        let weapon_frame: usize = data.parse("PLAYER", "weapon_frame")?;
+        if weapon_frame >= self.weapons[current_weapon].frames.len() {
+            return Err("Save doesn't match the weapon definitions".to_string());
+        }
+        let state = data.get("PLAYER", "weapon_state")?;
+
+        self.weapon_state = WeaponState::from_name(state).ok_or(format!("Unknown weapon state '{}'", state))?;
+        self.pos = data.parse_vector("PLAYER", "pos")?;
+        self.dir = data.parse_vector("PLAYER", "dir")?;
+        self.projection = data.parse_vector("PLAYER", "projection")?;
+        self.pitch = data.parse("PLAYER", "pitch")?;
+        self.movespeed = data.parse("PLAYER", "movespeed")?;
+        self.eye_height = data.parse("PLAYER", "eye_height")?;
+        self.jump_height = data.parse("PLAYER", "jump_height")?;
+        self.vertical_speed = data.parse("PLAYER", "vertical_speed")?;
+        self.frame_counter = data.parse("PLAYER", "frame_counter")?;
+        self.health = data.parse("PLAYER", "health")?;
+        self.armor = data.parse("PLAYER", "armor")?;
+        self.current_weapon = current_weapon;
+        self.weapon_frame = weapon_frame;
+        self.weapon_timer = data.parse("PLAYER", "weapon_timer")?;
+        self.weapon_cooldown = data.parse("PLAYER", "weapon_cooldown")?;
+        self.loaded = loaded;
+        self.message = data.get("PLAYER", "message")?.to_string();
+        self.message_timer = data.parse("PLAYER", "message_timer")?;
+        self.level_complete = data.parse("PLAYER", "level_complete")?;
+        self.inventory = Inventory::load_state(data)?;
+        Ok(())
+    }
+
+    pub fn muzzle_flash(&self) -> Option<PointLight> {
+        if self.flash_timer > 0 {
+            self.weapon().flash
         } else {
-            false // If out of bounds, don't allow movement
+            None
+        }
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        if self.message_timer > 0 {
+            Some(&self.message)
+        } else {
+            None
+        }
+    }
+
+    pub fn show_message(&mut self, message: String) {
+        self.message = message;
+        self.message_timer = MESSAGE_FRAMES;
+    }
+
+    fn use_tile(&mut self) {
+        // Doors and exits must be in the tile directly in front of the player
+        let target_x = (self.pos.x + self.dir.x).floor() as i32;
+        let target_y = (self.pos.y + self.dir.y).floor() as i32;
+
+        let mut map = self._map.borrow_mut();
+        if target_x < 0 || target_x >= map.size as i32 || target_y < 0 || target_y >= map.size as i32 {
+            return;
+        }
+
+        let (x, y) = (target_x as usize, target_y as usize);
+        let tile = map.map_data[y * map.size + x];
+        if tile == TILE_EXIT {
+            self.level_complete = true;
+            return;
+        }
+        if tile != TILE_DOOR {
+            return;
+        }
+
+        if let Err(key) = map.try_open_door(x, y, &self.inventory) {
+            drop(map);
+            self.show_message(format!("You need the {} key to open this door", key.name()));
         }
     }
 
+    fn collect_pickups(&mut self) {
+        let mut picked_up = Vec::new();
+
+        {
+            let mut map = self._map.borrow_mut();
+            for i in 0..map.pickups.len() {
+                let pickup = &map.pickups[i];
+                let dx = pickup.x - self.pos.x as f64;
+                let dy = pickup.y - self.pos.y as f64;
+                if pickup.is_taken || dx * dx + dy * dy >= 0.25 {
+                    continue;
+                }
+
+                // Ammo is left on the floor while the player can't carry any more of it
+                let wanted = match pickup.kind {
+                    PickupKind::Key(_) => true,
+                    PickupKind::Ammo(kind, _) => self.inventory.ammo(kind) < kind.max(),
+                };
+                if wanted {
+                    picked_up.push(map.take_pickup(i));
+                }
+            }
+        }
+
+        for kind in picked_up {
+            match kind {
+                PickupKind::Key(key) => {
+                    self.inventory.add_key(key);
+                    self.show_message(format!("Picked up the {} key", key.name()));
+                }
+                PickupKind::Ammo(kind, amount) => {
+                    let added = self.inventory.add_ammo(kind, amount);
+                    self.show_message(format!("Picked up {} {}", added, kind.name()));
+                }
+            }
+        }
+    }
+
+    fn is_valid_move(&self, new_pos: Vector2) -> bool {
+        // Walls, thin walls and anything outside the map block movement
+        !self._map.borrow().is_solid_at(new_pos.x as f64, new_pos.y as f64)
+    }
+
     fn rotate(&mut self, rot: f32) {
         let cos_rot = rot.cos();
         let sin_rot = rot.sin();
@@ -1,24 +1,55 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::f32::consts::TAU;
 use raylib::prelude::*;
 
-use rand::random;
-use crate::Player;
-use crate::Enemy;
-use crate::GameMap;
+use crate::player::Player;
+use crate::canvas::{color_to_u32, Canvas};
+use crate::gamemap::{Fog, GameMap};
+use crate::gamemap::{intersect_segment, is_see_through, CEILING_SKY, DEFAULT_CEILING_TEXTURE, DEFAULT_FLOOR_TEXTURE};
+use crate::gamemap::{TILE_DIAGONAL, TILE_DIAGONAL_FLIPPED, TILE_DOOR, TILE_EXIT, TILE_FENCE, TILE_GRATE};
+use crate::gamemap::{TILE_THIN_NORTH, TILE_THIN_WEST};
+use crate::lighting::{self, LightField, PointLight};
+use crate::texture::{self, Animation, MipChain, TextureFilter};
+
+const THIN_WALL_LIGHT_OFFSET: f32 = 0.125;
+
+// A wall a ray hit, with the rows of it that nearer walls leave visible
+struct WallHit {
+    tile: u8,
+    map_x: i32,
+    map_y: i32,
+    side: i32,
+    distance: f32, // Perpendicular to the camera plane
+    tex_u: f32, // How far along the wall the ray hits it, 0 to 1
+    facing: f32, // Ray direction along the wall's normal, small where the ray glances off it
+    thin: bool, // A thin or diagonal wall rather than a cell face
+    h: i32, // Height on screen of a one unit tall wall at this distance
+    height: f32,
+    rows: (i32, i32), // Buffer rows of the face
+    cap_rows: (i32, i32), // Buffer rows of the top, seen from above
+}
 
 pub struct Raycaster
 {
     buffer_width: i32,
     buffer_height: i32,
     player: Rc<RefCell<Player>>,   
-    textures: Vec<Rc<RefCell<Image>>>,  
+    textures: Rc<Vec<MipChain>>,
+    texture_filter: TextureFilter,
+    animations: Vec<Animation>,
+    frames: Vec<usize>, // Texture each texture index shows this frame
     _map: Rc<RefCell<GameMap>>,    
     pixelbuffer: Vec<u32>,
-    _framebuffer: RenderTexture2D,
-    z_buffer: Vec<f64>,
+    _framebuffer: Option<RenderTexture2D>, // None when rendering headless
+    wall_spans: Vec<Vec<(f32, i32, i32)>>, // Distance and buffer rows of each wall drawn in a column, nearest first
+    sky_mask: Vec<bool>, // Pixels where the ceiling is open to the sky
+    see_through_depth: Vec<f32>, // Per pixel, distance to the nearest see-through wall drawn over it
+    wall_hits: Vec<WallHit>, // Walls of the column being drawn, nearest first
     sprite_order: Vec<i32>,
-    sprite_distance: Vec<f64>
+    sprite_distance: Vec<f64>,
+    lights: LightField,
+    time: usize, // World tick being drawn, drives light flicker and texture animations
 }
 
 impl Raycaster
@@ -27,46 +58,74 @@ impl Raycaster
     (
         screen_width: i32,
         screen_height: i32,
-        _framebuffer: RenderTexture2D,
+        _framebuffer: Option<RenderTexture2D>,
         player: Rc<RefCell<Player>>,
-        textures: Vec<Rc<RefCell<Image>>>,  // Use Rc<RefCell<Image>> for mutable access
+        textures: Rc<Vec<MipChain>>,
+        animations: Vec<Animation>,
         _map: Rc<RefCell<GameMap>>
     ) -> Self
     {
         let pixelbuffer = vec![0; (screen_width * screen_height) as usize];
-        let z_buffer = vec![0.0; screen_width as usize]; // Stores depth values for each column
+        let wall_spans = vec![Vec::new(); screen_width as usize];
+        let sky_mask = vec![false; (screen_width * screen_height) as usize];
+        let see_through_depth = vec![f32::INFINITY; (screen_width * screen_height) as usize];
         let sprite_order = Vec::new(); // Will store indices of sorted sprites
         let sprite_distance = Vec::new(); // Will store distances of sprites from player
+        let lights = LightField::new(&_map.borrow());
 
         Raycaster {
             buffer_width: screen_width,
             buffer_height: screen_height,
             pixelbuffer,
             player,
+            frames: (0..textures.len()).collect(),
             textures,
+            texture_filter: TextureFilter::default(),
+            animations,
             _map,
             _framebuffer,
-            z_buffer,
+            wall_spans,
+            sky_mask,
+            see_through_depth,
+            wall_hits: Vec::new(),
             sprite_order,
-            sprite_distance
+            sprite_distance,
+            lights,
+            time: 0,
         }
     }
 
-    pub fn render_all(&mut self, d: &mut RaylibDrawHandle) {
+    // `time` is the world tick, so animations and flicker stop while the game is paused and keep
+    // up with a fast-forwarded demo
+    pub fn render_frame(&mut self, time: usize) {
+        self.time = time;
+        texture::current_frames(&mut self.frames, self.textures.len(), &self.animations, time);
+        self.update_lights();
         self.render_floor_ceiling();
         self.render_walls();
-        self.render_sprites(d);
+        self.render_sprites();
+    }
+
+    pub fn set_texture_filter(&mut self, filter: TextureFilter) {
+        self.texture_filter = filter;
+    }
+
+    // Overlays draw on top of the rendered view through this
+    pub fn canvas(&mut self) -> Canvas<'_> {
+        Canvas::new(&mut self.pixelbuffer, self.buffer_width, self.buffer_height)
+    }
 
-        let mut sprites = self._map.borrow_mut();
-        let vec = &mut sprites.sprites;
-        vec.retain(|sprite| sprite.is_destroyed == 0.0);
+    pub fn present(&mut self, d: &mut RaylibDrawHandle) {
+        let Some(framebuffer) = self._framebuffer.as_mut() else {
+            return;
+        };
 
-        self._framebuffer
+        framebuffer
             .texture_mut()
             .update_texture(bytemuck::cast_slice(&self.pixelbuffer));
         
         d.draw_texture_pro(
-            self._framebuffer.texture(), // Source texture
+            framebuffer.texture(), // Source texture
             rrect(0, 0, self.buffer_width as f32, -self.buffer_height as f32), // Source rectangle (flipped vertically)
             rrect(0, 0, d.get_screen_width() as f32, d.get_screen_height() as f32), // Destination rectangle (stretched to window)
             rvec2(0.0, 0.0), // Origin
@@ -75,9 +134,45 @@ impl Raycaster
         );
     }
 
-    fn color_to_u32(color: Color) -> u32 {
-        // Swap red and blue channels for BGRA format
-        ((color.a as u32) << 24) | ((color.b as u32) << 16) | ((color.g as u32) << 8) | (color.r as u32)
+    // Glowing sprites and the player's muzzle flash are the light sources of this frame
+    fn update_lights(&mut self) {
+        let map = self._map.borrow();
+        let player = self.player.borrow();
+
+        let mut lights: Vec<(f32, f32, PointLight)> = map
+            .sprites
+            .iter()
+            .filter(|sprite| !sprite.is_hidden)
+            .filter_map(|sprite| map.sprite_light(sprite.texture).map(|light| (sprite.x as f32, sprite.y as f32, light)))
+            .collect();
+        if let Some(flash) = player.muzzle_flash() {
+            lights.push((player.pos.x, player.pos.y, flash));
+        }
+
+        self.lights.build(&map, &lights, self.time);
+    }
+
+    fn tint(color: Color, tint: Color) -> Color {
+        Color::new(
+            ((color.r as u32 + tint.r as u32) / 2) as u8,
+            ((color.g as u32 + tint.g as u32) / 2) as u8,
+            ((color.b as u32 + tint.b as u32) / 2) as u8,
+            color.a,
+        )
+    }
+
+    // Fades towards the fog colour and darkens by `shade`, 1.0 keeps full brightness
+    fn fog(color: Color, fog: &Fog, distance: f32, shade: f32) -> Color {
+        let amount = fog.amount(distance);
+        if amount == 0.0 && shade == 1.0 {
+            return color;
+        }
+
+        let mix = |channel: u8, fog_channel: u8| {
+            let channel = channel as f32 * shade;
+            (channel + (fog_channel as f32 - channel) * amount) as u8
+        };
+        Color::new(mix(color.r, fog.color.r), mix(color.g, fog.color.g), mix(color.b, fog.color.b), color.a)
     }
 
     fn sort_sprites(order: &mut [i32], dist: &mut [f64]) {
@@ -93,22 +188,69 @@ impl Raycaster
         }
     }
 
-    fn render_floor_ceiling(&mut self) {
-        let mut floor_texture = self.textures[6].borrow_mut();  // Borrow the image immutably
-        let mut ceiling_texture = self.textures[1].borrow_mut();  // Borrow the image immutably
+    // Colour of a floor or ceiling texture at a point inside a cell, unknown textures fall back to `default`.
+    // `footprint` is how much of a cell one pixel covers.
+    fn surface_color(&self, texture: usize, default: usize, x: f32, y: f32, footprint: f32) -> Color {
+        let texture = if texture < self.textures.len() { texture } else { default };
+        let texture = &self.textures[self.frames[texture]];
+        let (width, height) = (texture.width() as f32, texture.height() as f32);
+        texture.sample(x * width, y * height, footprint * width, self.texture_filter)
+    }
 
+    // How much of a cell one pixel of a floor, ceiling or wall top covers, `distance` away and
+    // `rows` rows from the horizon. Pixels are stretched along the view, so this is the side of a
+    // square of the same area.
+    fn surface_footprint(&self, player: &Player, distance: f32, rows: f32) -> f32 {
+        let across = distance * 2.0 * (player.projection.x * player.projection.x + player.projection.y * player.projection.y).sqrt()
+            / self.buffer_width as f32;
+        let along = distance / rows;
+        (across * along).sqrt()
+    }
+
+    // Buffer row of the horizon. The pixel buffer is stored bottom row first, so rows above it
+    // are higher up on screen.
+    fn horizon(&self, player: &Player) -> i32 {
+        self.buffer_height / 2 - player.horizon_shift(self.buffer_height)
+    }
+
+    fn render_floor_ceiling(&mut self) {
         let player = self.player.borrow();
+        let map = self._map.borrow();
+        let fog = map.fog;
+        let horizon = self.horizon(&player);
+        let eye = player.view_height();
 
         let ray_dir_x0 = player.dir.x - player.projection.x;
         let ray_dir_y0 = player.dir.y - player.projection.y;
         let ray_dir_x1 = player.dir.x + player.projection.x;
         let ray_dir_y1 = player.dir.y + player.projection.y;
 
-        let pos_z = 0.5 * self.buffer_height as f32;
-
-        for y in (self.buffer_height / 2 + 1)..self.buffer_height {
-            let p = y as f32 - self.buffer_height as f32 / 2.0;
-            let row_distance = pos_z / p;
+        // Distance from the camera down to the floor and up to the ceiling, in pixels at one tile
+        let floor_z = eye * self.buffer_height as f32;
+        let ceiling_z = (1.0 - eye) * self.buffer_height as f32;
+        let half_height = 0.5 * self.buffer_height as f32;
+
+        // The sky only turns with the view, so each screen column shows one column of it
+        let sky = &self.textures[self.frames[map.textures.sky]];
+        let (sky_width, sky_height) = (sky.width(), sky.height());
+        let sky_columns: Vec<i32> = (0..self.buffer_width)
+            .map(|x| {
+                let xcam = 2.0 * x as f32 / self.buffer_width as f32 - 1.0;
+                let angle = (player.dir.y + player.projection.y * xcam).atan2(player.dir.x + player.projection.x * xcam);
+                ((angle / TAU).rem_euclid(1.0) * sky_width as f32) as i32 % sky_width
+            })
+            .collect();
+
+        self.sky_mask.fill(false);
+
+        for y in 0..self.buffer_height {
+            // Rows from the horizon up show the ceiling, rows below it the floor. Measuring from
+            // the pixel centre keeps the two rows next to the horizon off zero.
+            let is_ceiling = y >= horizon;
+            let p = (y as f32 - horizon as f32 + 0.5).abs();
+            let row_distance = if is_ceiling { ceiling_z } else { floor_z } / p;
+            let footprint = self.surface_footprint(&player, row_distance, p);
+            let sky_row = (((half_height - 0.5 - p) / half_height * sky_height as f32) as i32).clamp(0, sky_height - 1);
 
             let floor_step_x = row_distance * (ray_dir_x1 - ray_dir_x0) / self.buffer_width as f32;
             let floor_step_y = row_distance * (ray_dir_y1 - ray_dir_y0) / self.buffer_width as f32;
@@ -117,22 +259,31 @@ impl Raycaster
             let mut floor_y = player.pos.y + row_distance * ray_dir_y0;
 
             for x in 0..self.buffer_width {
-                let cell_x = floor_x as i32;
-                let cell_y = floor_y as i32;
+                let (cell_x, cell_y) = (floor_x.floor(), floor_y.floor());
+                let (floor_texture, ceiling_texture) = map.surface_textures(cell_x as i32, cell_y as i32);
+                let (fraction_x, fraction_y) = (floor_x - cell_x, floor_y - cell_y);
 
-                let tex_x = ((floor_x - cell_x as f32) * floor_texture.width as f32) as i32 & (floor_texture.width - 1);
-                let tex_y = ((floor_y - cell_y as f32) * floor_texture.height as f32) as i32 & (floor_texture.height - 1);
+                let light = self.lights.active.then(|| self.lights.sample(floor_x, floor_y));
+                let lit = |color: Color| light.map_or(color, |light| lighting::apply(color, light));
 
                 floor_x += floor_step_x;
                 floor_y += floor_step_y;
 
-                let floor_color = floor_texture.get_color(tex_x, tex_y);
-                // Store the floor pixel color in pixelbuffer
-                self.pixelbuffer[(y * self.buffer_width + x) as usize] = Self::color_to_u32(floor_color);
+                let index = (y * self.buffer_width + x) as usize;
+                let is_sky = is_ceiling && ceiling_texture == CEILING_SKY as usize;
+                self.sky_mask[index] = is_sky;
 
-                let ceiling_color = ceiling_texture.get_color(tex_x, tex_y);
-                // Store the ceiling pixel color in pixelbuffer (mirrored y-coordinate)
-                self.pixelbuffer[((self.buffer_height - y - 1) * self.buffer_width + x) as usize] = Self::color_to_u32(ceiling_color);
+                // Fog and lights don't reach the sky
+                let color = if !is_ceiling {
+                    let color = self.surface_color(floor_texture, DEFAULT_FLOOR_TEXTURE, fraction_x, fraction_y, footprint);
+                    Self::fog(lit(color), &fog, row_distance, 1.0)
+                } else if is_sky {
+                    sky.texel(sky_columns[x as usize], sky_row)
+                } else {
+                    let color = self.surface_color(ceiling_texture, DEFAULT_CEILING_TEXTURE, fraction_x, fraction_y, footprint);
+                    Self::fog(lit(color), &fog, row_distance, 1.0)
+                };
+                self.pixelbuffer[index] = color_to_u32(color);
             }
         }
     }
@@ -141,6 +292,13 @@ impl Raycaster
     fn render_walls(&mut self) {
         let player = self.player.borrow();
         let _map = self._map.borrow();
+        let horizon = self.horizon(&player);
+        let eye = player.view_height();
+        let tallest = _map.tallest_wall();
+        // Width of a column one unit in front of the camera
+        let column_span = 2.0 * (player.dir.x * player.projection.y - player.dir.y * player.projection.x).abs() / self.buffer_width as f32;
+
+        self.see_through_depth.fill(f32::INFINITY);
 
         for x in 0..self.buffer_width {
             let xcam = 2.0 * (x as f32) / self.buffer_width as f32 - 1.0;
@@ -163,17 +321,27 @@ impl Raycaster
             );
 
             let step = Vector2::new(dir.x.signum(), dir.y.signum());
-            let mut hit = (0, 0);
-
-            while hit.0 == 0 {
-                if sidedist.x < sidedist.y {
-                    sidedist.x += deltadist.x;
-                    ipos.x += step.x;
-                    hit.1 = 0;
-                } else {
-                    sidedist.y += deltadist.y;
-                    ipos.y += step.y;
-                    hit.1 = 1;
+            let mut side = 0;
+            // Rows below this are already taken by nearer walls, so walls further away only
+            // show above it
+            let mut clip = 0;
+
+            self.wall_spans[x as usize].clear();
+            self.wall_hits.clear();
+
+            // Walk the ray front to back, working out which rows of each wall are left visible
+            for cell in 0.. {
+                // The first cell is the camera's own, where only a thin or diagonal wall can be
+                if cell > 0 {
+                    side = if sidedist.x < sidedist.y {
+                        sidedist.x += deltadist.x;
+                        ipos.x += step.x;
+                        0
+                    } else {
+                        sidedist.y += deltadist.y;
+                        ipos.y += step.y;
+                        1
+                    };
                 }
 
                 let map_x = ipos.x as i32;
@@ -183,65 +351,188 @@ impl Raycaster
                     break;
                 }
 
-                hit.0 = _map.map_data[map_y as usize * _map.size + map_x as usize] as i32;
-            }
+                let tile = _map.map_data[map_y as usize * _map.size + map_x as usize];
+                let segment = _map.wall_segment(map_x, map_y);
+                let crossing = segment.and_then(|(a, b)| intersect_segment(pos.x as f64, pos.y as f64, dir.x as f64, dir.y as f64, a, b));
+                if tile == 0 || (segment.is_some() && crossing.is_none()) || (cell == 0 && segment.is_none()) {
+                    continue;
+                }
+                let see_through = is_see_through(tile);
+
+                // Perpendicular distance to the wall, which side it is shaded as, how far along it
+                // the ray hits and how squarely
+                let (dperp, side, tex_u, facing) = match (segment, crossing) {
+                    (Some((a, b)), Some((distance, along))) => {
+                        let (normal_x, normal_y) = ((a.1 - b.1) as f32, (b.0 - a.0) as f32);
+                        let facing = (dir.x * normal_x + dir.y * normal_y).abs() / (normal_x * normal_x + normal_y * normal_y).sqrt();
+                        (distance as f32, if a.1 == b.1 { 1 } else { 0 }, along as f32, facing)
+                    }
+                    _ => {
+                        let dperp = if side == 0 { sidedist.x - deltadist.x } else { sidedist.y - deltadist.y };
+                        let hit_pos = pos + dir * dperp;
+                        let tex_u = if side == 0 {
+                            hit_pos.y - hit_pos.y.floor()
+                        } else {
+                            hit_pos.x - hit_pos.x.floor()
+                        };
+
+                        let facing = if side == 0 { dir.x.abs() } else { dir.y.abs() };
+
+                        // Flip texture coordinate based on ray direction
+                        if (side == 0 && dir.x > 0.0) || (side == 1 && dir.y < 0.0) {
+                            (dperp, side, 1.0 - tex_u, facing)
+                        } else {
+                            (dperp, side, tex_u, facing)
+                        }
+                    }
+                };
+                let height = _map.wall_height(map_x, map_y);
+                let h = (self.buffer_height as f32 / dperp) as i32;
+                // The wall reaches `eye` below the camera and the rest above it
+                let bottom = horizon - (h as f32 * eye) as i32;
+                let top = horizon + (h as f32 * (height - eye)) as i32;
+                let rows = (bottom.max(clip).max(0), top.min(self.buffer_height - 1));
+
+                // From above a short wall its top is visible, stretching back to where the ray
+                // leaves the cell. See-through, thin and diagonal walls have no top.
+                let mut covered = top;
+                let mut cap_rows = (0, 0);
+                if eye > height && !see_through && segment.is_none() {
+                    let exit = sidedist.x.min(sidedist.y);
+                    covered = horizon - ((eye - height) * self.buffer_height as f32 / exit) as i32;
+                    cap_rows = (top.max(clip).max(0), covered.min(self.buffer_height - 1));
+                }
 
-            // Select the correct texture based on wall type
-            let texture_index = match hit.0 {
-                1 => 0,  // Texture for wall type 1
-                2 => 4,  // Texture for wall type 2
-                3 => 7, // Texture for wall type 3
-                4 => 2,  // Texture for wall type 4
-                _ => 0,  // Default texture
-            };
+                let thin = segment.is_some();
+                self.wall_hits.push(WallHit { tile, map_x, map_y, side, distance: dperp, tex_u, facing, thin, h, height, rows, cap_rows });
 
-            let mut texture = self.textures[texture_index].borrow_mut();
-            let tex_width = texture.width();
-            let tex_height = texture.height();
+                // Only solid walls hide what is behind them
+                if see_through {
+                    continue;
+                }
+                self.wall_spans[x as usize].push((dperp, bottom.max(0), covered.max(top).min(self.buffer_height - 1)));
+                clip = clip.max(rows.1).max(covered);
 
-            // Compute the perpendicular distance to the wall
-            let dperp = if hit.1 == 0 { sidedist.x - deltadist.x } else { sidedist.y - deltadist.y };
-            let h = (self.buffer_height as f32 / dperp) as i32;
-            let y0 = ((self.buffer_height / 2) - (h / 2)).max(0);
-            let y1 = ((self.buffer_height / 2) + (h / 2)).min(self.buffer_height - 1);
-
-            // Compute texture X coordinate
-            let hit_pos = pos + dir * dperp;
-            let mut tex_x = if hit.1 == 0 {
-                hit_pos.y - hit_pos.y.floor()
-            } else {
-                hit_pos.x - hit_pos.x.floor()
-            };
-
-            // Flip texture coordinate based on ray direction
-            if (hit.1 == 0 && dir.x > 0.0) || (hit.1 == 1 && dir.y < 0.0) {
-                tex_x = 1.0 - tex_x;
+                // Walls behind this one could only show above the top of the screen, or this is
+                // as tall as walls get and the camera is below its top
+                if clip >= self.buffer_height - 1 || (height >= tallest && eye <= height) {
+                    break;
+                }
             }
 
-            let tex_x = (tex_x * tex_width as f32) as i32;
-            let step = tex_height as f32 / h as f32;
-            let mut tex_pos = (y0 as f32 - self.buffer_height as f32 / 2.0 + h as f32 / 2.0) * step;
+            // Then draw back to front, so see-through walls go over whatever shows through them
+            for hit in self.wall_hits.iter().rev() {
+                // Select the correct texture based on wall type
+                let texture_index = match hit.tile {
+                    1 => 0,  // Texture for wall type 1
+                    2 => 4,  // Texture for wall type 2
+                    3 => 7, // Texture for wall type 3
+                    4 => 2,  // Texture for wall type 4
+                    TILE_DOOR => 1, // Doors are wooden
+                    TILE_EXIT => 5, // Exits stand out in coloured stone
+                    TILE_GRATE => _map.textures.grate,
+                    TILE_FENCE => _map.textures.fence,
+                    TILE_THIN_NORTH..=TILE_THIN_WEST => 3, // Thin walls are purple stone
+                    TILE_DIAGONAL | TILE_DIAGONAL_FLIPPED => 6, // Diagonals are blue stone
+                    _ => 0,  // Default texture
+                };
+                let see_through = is_see_through(hit.tile);
+
+                // Locked doors are tinted with the colour of the key they need
+                let door_tint = if hit.tile == TILE_DOOR {
+                    _map.door_at(hit.map_x as usize, hit.map_y as usize)
+                        .and_then(|door| door.key)
+                        .map(|key| key.color())
+                } else {
+                    None
+                };
+
+                let texture = &self.textures[self.frames[texture_index]];
+                let tex_width = texture.width();
+                let tex_height = texture.height();
+
+                // Above this row a tall wall rises past the ceiling and only shows where the sky is open
+                let ceiling_row = horizon + (hit.h as f32 * (1.0 - eye)) as i32;
+
+                let hit_pos = pos + dir * hit.distance;
+                // Thin and diagonal walls have nothing baked, so they take the light just in front of them
+                let light = self.lights.active.then(|| {
+                    if hit.thin {
+                        let front = hit_pos - dir * (THIN_WALL_LIGHT_OFFSET / (dir.x * dir.x + dir.y * dir.y).sqrt());
+                        self.lights.sample(front.x, front.y)
+                    } else {
+                        self.lights.sample_wall(hit_pos, hit.side, step)
+                    }
+                });
+
+                let tex_x = hit.tex_u * tex_width as f32;
+                let tex_step = tex_height as f32 / hit.h as f32;
+                // Texels per pixel up the column and across to the next one, which grows as the
+                // ray glances along the wall
+                let across = tex_width as f32 * hit.distance * column_span / hit.facing;
+                let texels_per_pixel = (tex_step * across).sqrt();
+                let shade = if hit.side == 1 { _map.fog.side_shade } else { 1.0 };
+                // The texture starts at the foot of the wall and repeats up taller walls. Rows are
+                // sampled at their middle.
+                let mut tex_pos = (hit.rows.0 as f32 + 0.5 - horizon as f32 + hit.h as f32 * eye) * tex_step;
+
+                for y in hit.rows.0..hit.rows.1 {
+                    let tex_y = tex_height as f32 - tex_pos;
+                    tex_pos += tex_step;
+
+                    let index = (y * self.buffer_width + x) as usize;
+                    if y >= ceiling_row && !self.sky_mask[index] {
+                        continue;
+                    }
 
-            for y in y0..y1 {
-                let tex_y = (tex_height - 1 - (tex_pos as i32)) & (tex_height - 1);
-                tex_pos += step;
+                    let mut color = texture.sample(tex_x, tex_y, texels_per_pixel, self.texture_filter);
+                    if see_through {
+                        // Filtered edges of the holes are partly transparent
+                        if color.a < 128 {
+                            continue;
+                        }
+                        self.see_through_depth[index] = hit.distance;
+                    }
+                    if let Some(tint) = door_tint {
+                        color = Self::tint(color, tint);
+                    }
+                    if let Some(light) = light {
+                        color = lighting::apply(color, light);
+                    }
+                    color = Self::fog(color, &_map.fog, hit.distance, shade);
 
-                let color = texture.get_color(tex_x, tex_y);
+                    self.pixelbuffer[index] = color_to_u32(color);
+                }
 
-                self.pixelbuffer[(y * self.buffer_width + x) as usize] = Self::color_to_u32(color);
-            }
+                let drop = (eye - hit.height) * self.buffer_height as f32;
+                for y in hit.cap_rows.0..hit.cap_rows.1 {
+                    let distance = drop / (horizon - y) as f32;
+                    let point = pos + dir * distance;
+                    let cap_x = (point.x - point.x.floor()) * tex_width as f32;
+                    let cap_y = (point.y - point.y.floor()) * tex_height as f32;
+                    let footprint = self.surface_footprint(&player, distance, (horizon - y) as f32);
+
+                    let mut color = texture.sample(cap_x, cap_y, footprint * tex_width as f32, self.texture_filter);
+                    if let Some(tint) = door_tint {
+                        color = Self::tint(color, tint);
+                    }
+                    if self.lights.active {
+                        color = lighting::apply(color, self.lights.sample(point.x, point.y));
+                    }
+                    color = Self::fog(color, &_map.fog, distance, 1.0);
 
-            self.z_buffer[x as usize] = dperp as f64; 
+                    self.pixelbuffer[(y * self.buffer_width + x) as usize] = color_to_u32(color);
+                }
+            }
         }
     }
 
-    fn render_sprites(&mut self,d: &mut RaylibDrawHandle) {
-        let mut sprites = self._map.borrow_mut();
-        let map_size = sprites.size;
-        let _map_data = sprites.map_data.clone();
-        let vec = &mut sprites.sprites;
+    fn render_sprites(&mut self) {
+        let sprites = self._map.borrow();
+        let vec = &sprites.sprites;
         let player = self.player.borrow();
         let pos = player.pos;
+        let horizon = self.horizon(&player);
 
         self.sprite_order.resize(vec.len(), 0);
         self.sprite_distance.resize(vec.len(), 0.0);
@@ -256,75 +547,18 @@ impl Raycaster
         let w = self.buffer_width as f32;
         let h = self.buffer_height as f32;
 
-        let mut sprite_ui_index = 0;
-
         for i in 0..vec.len() {
             let sprite_index = self.sprite_order[i] as usize;
-            let sprite = &mut vec[sprite_index]; // Mutable reference
-
-            let mut texture = self.textures[sprite.texture as usize].borrow_mut();
-            let tex_width = texture.width;
-            let tex_height = texture.height;
-
-            if sprite.is_projectile == 1.0 {
-                let dir_x = sprite.dir_x;
-                let dir_y = sprite.dir_y;
-
-                // Normalize direction
-                let length = (dir_x * dir_x + dir_y * dir_y).sqrt();
-                if length != 0.0 {
-                    // Add noise to the direction vector to introduce more deviation
-                    let noise_x = (random::<f32>() - 0.5) * 0.2; // Adjust the multiplier for more/less noise
-                    let noise_y = (random::<f32>() - 0.5) * 0.2;
+            let sprite = &vec[sprite_index];
 
-                    // Apply the noise to the direction components
-                    let vx = (dir_x / length + noise_x as f64) * 0.1;
-                    let vy = (dir_y /length + noise_y as f64) * 0.1;
-
-                    let dt = d.get_time().clamp(0.0, 1.0); 
-
-                    sprite.x += vx * dt; 
-                    sprite.y += vy * dt;
-
-                    let grid_x = sprite.x as usize; 
-                    let grid_y = sprite.y as usize;
-
-                    let index = grid_y * map_size + grid_x; 
-
-                    if index < _map_data.len() && _map_data[index] != 0 {
-                        sprite.is_destroyed = 1.0; 
-                    }
-                }
-            }
-
-            if sprite.is_ui == 1.0 {
-                // Define position for the projectile UI on the right side of the screen
-                let screen_x = (w * 0.25) as i32; // Right side of the screen
-                // Use i32 for the Y position, casting the index safely
-                let screen_y = (h * 0.001 + (sprite_ui_index as f32 * (tex_height as f32 + 5.0))) as i32;
-                sprite_ui_index += 1;
-
-                // Iterate over the sprite texture
-                for y in 0..tex_height {
-                    for x in 0..tex_width {
-                        let color = texture.get_color(x, tex_height - 1 - y);
-                        if color.a > 0 { // Ignore transparent pixels
-                            let pixel_x = screen_x + x;
-                            let pixel_y = screen_y + y;
-
-                            // Ensure coordinates are within the buffer's bounds
-                            if pixel_x >= 0 && pixel_x < self.buffer_width &&
-                               pixel_y >= 0 && pixel_y < self.buffer_height {
-                                // Convert to buffer index and set the pixel color
-                                let buffer_index = pixel_y as usize * self.buffer_width as usize + pixel_x as usize;
-                                self.pixelbuffer[buffer_index] = Self::color_to_u32(color);
-                            }
-                        }
-                    }
-                }
-                continue; // Skip 3D rendering for this projectile
+            if sprite.is_hidden {
+                continue;
             }
 
+            let texture = &self.textures[self.frames[sprite.texture as usize]];
+            let light = self.lights.active.then(|| self.lights.sample(sprite.x as f32, sprite.y as f32));
+            let tex_width = texture.width();
+            let tex_height = texture.height();
 
             let sprite_x = sprite.x as f32 - pos.x;
             let sprite_y = sprite.y as f32 - pos.y;
@@ -339,10 +573,12 @@ impl Raycaster
 
             let sprite_screen_x = ((w / 2.0) * (1.0 + transform_x / transform_y)).round() as i32;
             let sprite_height = (h / transform_y).abs() as i32;
-            let v_move_screen = 0;
+            // Sprites stand on the floor like walls do, so they move against the camera height
+            let v_move_screen = ((0.5 - player.view_height()) * sprite_height as f32) as i32;
+            let center_y = horizon + v_move_screen;
 
-            let draw_start_y = (-sprite_height / 2 + self.buffer_height / 2 ).clamp(0, self.buffer_height);
-            let draw_end_y = (sprite_height / 2 + self.buffer_height / 2 ).clamp(0, self.buffer_height - 1);
+            let draw_start_y = (-sprite_height / 2 + center_y).clamp(0, self.buffer_height);
+            let draw_end_y = (sprite_height / 2 + center_y).clamp(0, self.buffer_height - 1);
 
             let sprite_width = (h / transform_y).abs() as i32;
             let draw_start_x = (-sprite_width / 2 + sprite_screen_x).clamp(0, self.buffer_width);
@@ -351,16 +587,24 @@ impl Raycaster
             for stripe in draw_start_x..draw_end_x {
                 let tex_x = (((stripe - (-sprite_width / 2 + sprite_screen_x)) * tex_width) / sprite_width).clamp(0, tex_width - 1);
 
-                if transform_y > 0.0 && (stripe as usize) < self.z_buffer.len() && (transform_y as f64) < self.z_buffer[stripe as usize] {
-                    for y in draw_start_y..draw_end_y {
-                        let d = (y - v_move_screen) * 256 - (self.buffer_height * 128) + (sprite_height * 128);
-                        let tex_y = tex_height - 1 - ((d * tex_height) / sprite_height / 256).clamp(0, tex_height - 1);
-
-                        let color = texture.get_color(tex_x, tex_y);
-                        if color.a > 0 && !(color.r == 0 && color.g == 0 && color.b == 0) {
-                            let buffer_index = (y * self.buffer_width + stripe) as usize;
-                            self.pixelbuffer[buffer_index] = Self::color_to_u32(color);
-                        }
+                // Solid walls only hide the rows they cover, so sprites show over shorter walls
+                let spans = &self.wall_spans[stripe as usize];
+                for y in draw_start_y..draw_end_y {
+                    if spans.iter().any(|&(distance, from, to)| distance < transform_y && (from..to).contains(&y)) {
+                        continue;
+                    }
+                    // Behind a see-through wall, only the holes in it show the sprite
+                    if self.see_through_depth[(y * self.buffer_width + stripe) as usize] < transform_y {
+                        continue;
+                    }
+                    let d = (y - center_y) * 256 + (sprite_height * 128);
+                    let tex_y = tex_height - 1 - ((d * tex_height) / sprite_height / 256).clamp(0, tex_height - 1);
+
+                    let color = texture.texel(tex_x, tex_y);
+                    if color.a > 0 && !(color.r == 0 && color.g == 0 && color.b == 0) {
+                        let color = light.map_or(color, |light| lighting::apply(color, light));
+                        let buffer_index = (y * self.buffer_width + stripe) as usize;
+                        self.pixelbuffer[buffer_index] = color_to_u32(Self::fog(color, &sprites.fog, transform_y, 1.0));
                     }
                 }
             }
@@ -0,0 +1,225 @@
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use raylib::prelude::*;
+
+use crate::settings;
+
+// Bump whenever the meaning of a saved value changes; older saves are refused, not guessed at
+pub const SAVE_VERSION: u32 = 4;
+const HEADER: &str = "RAYCASTER_SAVE";
+pub const QUICKSAVE_SLOT: &str = "quick";
+
+// Saves sit in a `saves` directory next to the settings file
+pub fn slot_path(slot: &str) -> PathBuf {
+    let settings_path = settings::default_path();
+    let dir = settings_path.parent().unwrap_or(Path::new("."));
+    dir.join("saves").join(format!("{}.sav", slot))
+}
+
+// Save files use the same `[SECTION]` and `key = value` layout as the other data files.
+// A key may repeat within a section to store a list, e.g. one `sprite` line per sprite.
+// Floats are written with Rust's shortest round-trip formatting, so loading restores
+// exactly the values that were saved.
+#[derive(Default)]
+pub struct SaveData {
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl SaveData {
+    pub fn section(&mut self, name: &str) {
+        self.sections.push((name.to_string(), Vec::new()));
+    }
+
+    pub fn put(&mut self, key: &str, value: impl Display) {
+        if self.sections.is_empty() {
+            self.section("");
+        }
+        let value = value.to_string().replace('\n', " ");
+        self.sections.last_mut().unwrap().1.push((key.to_string(), value));
+    }
+
+    pub fn put_list<T: Display>(&mut self, key: &str, values: &[T]) {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        self.put(key, values.join(", "));
+    }
+
+    fn entries(&self, section: &str) -> Vec<&(String, String)> {
+        self.sections
+            .iter()
+            .filter(|(name, _)| name == section)
+            .flat_map(|(_, entries)| entries.iter())
+            .collect()
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Result<&str, String> {
+        self.entries(section)
+            .into_iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+            .ok_or(format!("Missing '{}' in [{}]", key, section))
+    }
+
+    // Every value stored under a repeated key, in the order it was written
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<&str> {
+        self.entries(section)
+            .into_iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn parse<T: FromStr>(&self, section: &str, key: &str) -> Result<T, String> {
+        let value = self.get(section, key)?;
+        value.parse().map_err(|_| format!("Bad value '{}' for '{}' in [{}]", value, key, section))
+    }
+
+    pub fn parse_list<T: FromStr>(&self, section: &str, key: &str) -> Result<Vec<T>, String> {
+        parse_list(self.get(section, key)?)
+    }
+
+    pub fn parse_vector(&self, section: &str, key: &str) -> Result<Vector2, String> {
+        match self.parse_list::<f32>(section, key)?[..] {
+            [x, y] => Ok(Vector2::new(x, y)),
+            _ => Err(format!("'{}' in [{}] needs two values", key, section)),
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut content = format!("{} {}\n", HEADER, SAVE_VERSION);
+        for (name, entries) in &self.sections {
+            content += &format!("\n[{}]\n", name);
+            for (key, value) in entries {
+                content += &format!("{} = {}\n", key, value);
+            }
+        }
+        content
+    }
+
+    pub fn deserialize(content: &str) -> Result<Self, String> {
+        let mut lines = content.lines();
+
+        let header = lines.next().unwrap_or("");
+        let version = header
+            .strip_prefix(HEADER)
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or("Not a save file")?;
+        if version != SAVE_VERSION {
+            return Err(format!("Save version {} is not supported (expected {})", version, SAVE_VERSION));
+        }
+
+        let mut data = SaveData::default();
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                data.section(&line[1..line.len() - 1]);
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("Bad line '{}'", line));
+            };
+            data.put(key.trim(), value.trim());
+        }
+
+        Ok(data)
+    }
+
+    // Hash of the serialized save, two worlds with the same hash are in the same state
+    pub fn hash(&self) -> u64 {
+        fnv1a(self.serialize().as_bytes())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|error| error.to_string())?;
+        }
+        fs::write(path, self.serialize()).map_err(|error| error.to_string())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Self::deserialize(&content)
+    }
+}
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+pub fn parse_list<T: FromStr>(value: &str) -> Result<Vec<T>, String> {
+    if value.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    value
+        .split(',')
+        .map(|item| item.trim().parse().map_err(|_| format!("Bad list value '{}'", item.trim())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Difficulty, Game, World};
+    use crate::input::{Action, InputFrame};
+    use crate::settings::Settings;
+    use crate::{texture, weapon};
+
+    fn load_world() -> World {
+        let weapons = weapon::load_weapons("res/weapons.txt").unwrap();
+        let textures = texture::load_manifest(texture::MANIFEST_PATH).unwrap().builtin;
+        Game::load_level("res/level_1.txt", &weapons, &textures, &Settings::default(), Difficulty::Normal, 7)
+    }
+
+    // Walks, turns, looks up and shoots so the save has more than the starting state in it
+    fn play(world: &World, ticks: usize) {
+        for tick in 0..ticks {
+            let fire = if tick % 20 == 0 { Action::Fire.bit() } else { 0 };
+            world.tick(&InputFrame {
+                held: Action::MoveForward.bit() | fire,
+                pressed: fire,
+                turn: 0.02,
+                look: 0.005,
+                weapon_slot: None,
+            });
+        }
+    }
+
+    #[test]
+    fn world_survives_a_save_and_load() {
+        let world = load_world();
+        play(&world, 90);
+        let mut data = SaveData::default();
+        world.save_state(&mut data);
+
+        let loaded = load_world();
+        loaded.load_state(&SaveData::deserialize(&data.serialize()).unwrap()).unwrap();
+        assert_eq!(loaded.state_hash(), world.state_hash());
+
+        // Both carry on the same way from there
+        play(&world, 60);
+        play(&loaded, 60);
+        assert_eq!(loaded.state_hash(), world.state_hash());
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let mut data = SaveData::default();
+        load_world().save_state(&mut data);
+        let content = data.serialize();
+        assert!(SaveData::deserialize(&content).is_ok());
+
+        for version in [SAVE_VERSION - 1, SAVE_VERSION + 1] {
+            let old = content.replacen(&format!("{} {}", HEADER, SAVE_VERSION), &format!("{} {}", HEADER, version), 1);
+            let error = SaveData::deserialize(&old).err().unwrap();
+            assert!(error.contains("not supported"), "{}", error);
+        }
+        assert!(SaveData::deserialize("[PLAYER]\nhealth = 100\n").is_err());
+    }
+}
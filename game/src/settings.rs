@@ -0,0 +1,297 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::input::{default_bindings, Action, Binding, ACTIONS, REBINDABLE};
+use crate::texture::TextureFilter;
+
+#[derive(Clone)]
+pub struct Settings {
+    pub window_width: i32,
+    pub window_height: i32,
+    pub vsync: bool,
+    pub mouse_sensitivity: f32, // Radians per pixel of mouse movement
+    pub fov: f32, // Length of the camera plane, 0.66 is about 66 degrees
+    pub buffer_width: i32, // Internal resolution the view is rendered at
+    pub buffer_height: i32,
+    pub volume: f32,
+    pub texture_filter: TextureFilter,
+    pub bindings: Vec<(Action, Binding)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            window_width: 1024,
+            window_height: 768,
+            vsync: true,
+            mouse_sensitivity: 0.003,
+            fov: 0.66,
+            buffer_width: 550,
+            buffer_height: 350,
+            volume: 1.0,
+            texture_filter: TextureFilter::default(),
+            bindings: default_bindings(),
+        }
+    }
+}
+
+// Allowed ranges, anything outside is clamped with a warning
+const SENSITIVITY_RANGE: (f32, f32) = (0.0005, 0.02);
+const FOV_RANGE: (f32, f32) = (0.4, 1.2);
+const WINDOW_MIN: (i32, i32) = (320, 200);
+const BUFFER_MIN: (i32, i32) = (160, 100);
+const BUFFER_MAX: (i32, i32) = (1920, 1080);
+
+// settings.txt in the per-user config directory, or the working directory if there is none
+pub fn default_path() -> PathBuf {
+    let config_dir = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    match config_dir {
+        Some(dir) => dir.join("raycaster").join("settings.txt"),
+        None => PathBuf::from("settings.txt"),
+    }
+}
+
+fn clamp_setting<T: PartialOrd + Copy + std::fmt::Display>(name: &str, value: &mut T, min: T, max: T, warnings: &mut Vec<String>) {
+    if !(min..=max).contains(value) {
+        let clamped = if *value < min { min } else { max };
+        warnings.push(format!("{} = {} is outside {}..{}, using {}", name, value, min, max, clamped));
+        *value = clamped;
+    }
+}
+
+// NaN and infinity parse as floats but fit no range, so they fall back to the default
+fn clamp_float(name: &str, value: &mut f32, min: f32, max: f32, default: f32, warnings: &mut Vec<String>) {
+    if !value.is_finite() {
+        warnings.push(format!("{} = {} is not a usable number, using {}", name, value, default));
+        *value = default;
+    }
+    clamp_setting(name, value, min, max, warnings);
+}
+
+impl Settings {
+    // A missing file just means defaults; the file is created the first time something changes
+    pub fn load(file_path: &Path) -> Self {
+        match fs::read_to_string(file_path) {
+            Ok(content) => Self::parse(&content, file_path),
+            Err(_) => Settings::default(),
+        }
+    }
+
+    // `file_path` only names the file in warnings
+    fn parse(content: &str, file_path: &Path) -> Self {
+        let mut settings = Settings::default();
+        let mut section = String::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                section = line.to_string();
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            if section == "[BINDINGS]" {
+                let Some(action) = Action::from_name(key) else {
+                    eprintln!("Unknown action '{}' in {}", key, file_path.display());
+                    continue;
+                };
+                let bindings: Vec<Binding> = value.split(',').filter_map(|name| Binding::parse(name.trim())).collect();
+                if bindings.is_empty() {
+                    eprintln!("No usable binding for '{}' in {}", key, file_path.display());
+                    continue;
+                }
+                settings.bindings.retain(|(bound, _)| *bound != action);
+                settings.bindings.extend(bindings.into_iter().map(|binding| (action, binding)));
+                continue;
+            }
+
+            match key {
+                "window_width" => settings.window_width = value.parse().unwrap_or(settings.window_width),
+                "window_height" => settings.window_height = value.parse().unwrap_or(settings.window_height),
+                "vsync" => settings.vsync = value == "1" || value == "true",
+                "mouse_sensitivity" => settings.mouse_sensitivity = value.parse().unwrap_or(settings.mouse_sensitivity),
+                "fov" => settings.fov = value.parse().unwrap_or(settings.fov),
+                "buffer_width" => settings.buffer_width = value.parse().unwrap_or(settings.buffer_width),
+                "buffer_height" => settings.buffer_height = value.parse().unwrap_or(settings.buffer_height),
+                "volume" => settings.volume = value.parse().unwrap_or(settings.volume),
+                "texture_filter" => match TextureFilter::from_name(value) {
+                    Some(filter) => settings.texture_filter = filter,
+                    None => eprintln!("Unknown texture filter '{}' in {}", value, file_path.display()),
+                },
+                _ => eprintln!("Unknown setting '{}' in {}", key, file_path.display()),
+            }
+        }
+
+        for warning in settings.validate() {
+            eprintln!("settings warning: {}", warning);
+        }
+        settings
+    }
+
+    // Clamps every value into a usable range and reports what had to change
+    pub fn validate(&mut self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let defaults = Settings::default();
+
+        clamp_setting("window_width", &mut self.window_width, WINDOW_MIN.0, i32::MAX, &mut warnings);
+        clamp_setting("window_height", &mut self.window_height, WINDOW_MIN.1, i32::MAX, &mut warnings);
+        clamp_float(
+            "mouse_sensitivity",
+            &mut self.mouse_sensitivity,
+            SENSITIVITY_RANGE.0,
+            SENSITIVITY_RANGE.1,
+            defaults.mouse_sensitivity,
+            &mut warnings,
+        );
+        clamp_float("fov", &mut self.fov, FOV_RANGE.0, FOV_RANGE.1, defaults.fov, &mut warnings);
+        clamp_setting("buffer_width", &mut self.buffer_width, BUFFER_MIN.0, BUFFER_MAX.0, &mut warnings);
+        clamp_setting("buffer_height", &mut self.buffer_height, BUFFER_MIN.1, BUFFER_MAX.1, &mut warnings);
+        clamp_float("volume", &mut self.volume, 0.0, 1.0, defaults.volume, &mut warnings);
+
+        // Every action needs at least one binding, otherwise menus or movement could be lost
+        for action in ACTIONS {
+            if self.bindings_for(action).is_empty() {
+                warnings.push(format!("'{}' has no binding, using the default", action.name()));
+                self.bindings.extend(default_bindings().into_iter().filter(|(bound, _)| *bound == action));
+            }
+        }
+
+        warnings
+    }
+
+    pub fn save(&self, file_path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = file_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut content = String::from("# Written by the game whenever an option changes\n\n[SETTINGS]\n");
+        content += &format!("window_width = {}\n", self.window_width);
+        content += &format!("window_height = {}\n", self.window_height);
+        content += &format!("vsync = {}\n", self.vsync);
+        content += &format!("mouse_sensitivity = {}\n", self.mouse_sensitivity);
+        content += &format!("fov = {}\n", self.fov);
+        content += &format!("buffer_width = {}\n", self.buffer_width);
+        content += &format!("buffer_height = {}\n", self.buffer_height);
+        content += &format!("volume = {}\n", self.volume);
+        content += &format!("texture_filter = {}\n", self.texture_filter.name());
+
+        content += "\n[BINDINGS]\n";
+        for action in ACTIONS {
+            let names: Vec<String> = self.bindings_for(action).iter().map(|binding| binding.name()).collect();
+            if !names.is_empty() {
+                content += &format!("{} = {}\n", action.name(), names.join(", "));
+            }
+        }
+
+        fs::write(file_path, content)
+    }
+
+    pub fn bindings_for(&self, action: Action) -> Vec<Binding> {
+        self.bindings
+            .iter()
+            .filter(|(bound, _)| *bound == action)
+            .map(|(_, binding)| *binding)
+            .collect()
+    }
+
+    // Replaces every binding of `action`, and takes `binding` away from any other gameplay
+    // action so one key never does two things. An action left without any binding that way
+    // gets the old binding of `action`, so the two swap keys.
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let previous = self.bindings_for(action).into_iter().find(|&old| old != binding);
+        let displaced: Vec<Action> = REBINDABLE
+            .into_iter()
+            .filter(|&other| other != action && self.bindings_for(other) == [binding])
+            .collect();
+
+        self.bindings.retain(|(bound, existing)| {
+            *bound != action && !(*existing == binding && REBINDABLE.contains(bound))
+        });
+        self.bindings.push((action, binding));
+        if let Some(previous) = previous {
+            self.bindings.extend(displaced.into_iter().map(|other| (other, previous)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use raylib::prelude::*;
+
+    use super::*;
+
+    fn parse(content: &str) -> Settings {
+        Settings::parse(content, Path::new("test_settings.txt"))
+    }
+
+    #[test]
+    fn unusable_numbers_fall_back_to_the_defaults() {
+        let defaults = Settings::default();
+        let settings = parse("[SETTINGS]\nfov = NaN\nmouse_sensitivity = inf\nvolume = -infinity\nbuffer_height = tall\n");
+        assert_eq!(settings.fov, defaults.fov);
+        assert_eq!(settings.mouse_sensitivity, defaults.mouse_sensitivity);
+        assert_eq!(settings.volume, defaults.volume);
+        assert_eq!(settings.buffer_height, defaults.buffer_height);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let settings = parse(
+            "[SETTINGS]\nfov = 3\nmouse_sensitivity = 0\nvolume = 1.5\nwindow_width = 10\nbuffer_width = 99999\nbuffer_height = -5\n",
+        );
+        assert_eq!(settings.fov, FOV_RANGE.1);
+        assert_eq!(settings.mouse_sensitivity, SENSITIVITY_RANGE.0);
+        assert_eq!(settings.volume, 1.0);
+        assert_eq!(settings.window_width, WINDOW_MIN.0);
+        assert_eq!(settings.buffer_width, BUFFER_MAX.0);
+        assert_eq!(settings.buffer_height, BUFFER_MIN.1);
+
+        let mut settings = Settings { fov: f32::NAN, volume: -0.5, ..Settings::default() };
+        let warnings = settings.validate();
+        assert_eq!(warnings, ["fov = NaN is not a usable number, using 0.66", "volume = -0.5 is outside 0..1, using 0"]);
+    }
+
+    #[test]
+    fn rebinding_swaps_with_an_action_left_unbound() {
+        let mut settings = Settings::default();
+        let fire = Binding::Mouse(MouseButton::MOUSE_BUTTON_LEFT);
+        let forward = Binding::Key(KeyboardKey::KEY_W);
+
+        settings.rebind(Action::Fire, forward);
+        assert_eq!(settings.bindings_for(Action::Fire), [forward]);
+        assert_eq!(settings.bindings_for(Action::MoveForward), [fire]);
+        assert!(settings.validate().is_empty());
+    }
+
+    #[test]
+    fn rebinding_leaves_other_bindings_of_the_displaced_action() {
+        let mut settings = Settings::default();
+        let forward = Binding::Key(KeyboardKey::KEY_W);
+        let up = Binding::Key(KeyboardKey::KEY_UP);
+        settings.bindings.push((Action::MoveForward, up));
+
+        settings.rebind(Action::Jump, forward);
+        assert_eq!(settings.bindings_for(Action::Jump), [forward]);
+        assert_eq!(settings.bindings_for(Action::MoveForward), [up]);
+        // The old jump key isn't handed to anything
+        assert!(settings.bindings.iter().all(|&(_, binding)| binding != Binding::Key(KeyboardKey::KEY_SPACE)));
+    }
+}
@@ -0,0 +1,346 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use raylib::prelude::*;
+
+use crate::save::parse_list;
+
+pub const MANIFEST_PATH: &str = "res/textures.txt";
+
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub texture: usize, // Index that shows the animation wherever it is used
+    pub frames: Vec<usize>, // Textures shown in turn
+    pub frame_time: usize, // World ticks each of them stays up
+}
+
+// How walls, floors and ceilings are sampled. Sprites and the sky always use full size texels.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum TextureFilter {
+    Nearest, // Full size texels everywhere, distant surfaces shimmer
+    #[default]
+    Mipmapped, // Texels of a smaller copy far away, as blocky as ever up close
+    Bilinear, // Like Mipmapped, blending the four texels around each point
+}
+
+pub const TEXTURE_FILTERS: [TextureFilter; 3] = [TextureFilter::Nearest, TextureFilter::Mipmapped, TextureFilter::Bilinear];
+
+impl TextureFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        TEXTURE_FILTERS.iter().copied().find(|filter| filter.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TextureFilter::Nearest => "nearest",
+            TextureFilter::Mipmapped => "mipmapped",
+            TextureFilter::Bilinear => "bilinear",
+        }
+    }
+
+    // Shown on the options screen
+    pub fn label(self) -> &'static str {
+        match self {
+            TextureFilter::Nearest => "Nearest",
+            TextureFilter::Mipmapped => "Mipmapped",
+            TextureFilter::Bilinear => "Bilinear",
+        }
+    }
+}
+
+// One size of a texture, rows from the top like Image::get_color
+#[derive(Clone, Debug)]
+pub struct MipLevel {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<Color>,
+}
+
+impl MipLevel {
+    // Coordinates wrap, so surfaces repeat the texture
+    pub fn texel(&self, x: i32, y: i32) -> Color {
+        self.pixels[(y.rem_euclid(self.height) * self.width + x.rem_euclid(self.width)) as usize]
+    }
+
+    // Half the size, each texel averaging the ones it covers. Colours are weighted by alpha so
+    // the colour hidden under transparent texels doesn't bleed into the edges of a grate.
+    fn halved(&self) -> MipLevel {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (mut r, mut g, mut b, mut a, mut count) = (0, 0, 0, 0, 0);
+                for source_y in y * self.height / height..(y + 1) * self.height / height {
+                    for source_x in x * self.width / width..(x + 1) * self.width / width {
+                        let color = self.texel(source_x, source_y);
+                        let alpha = color.a as u32;
+                        r += color.r as u32 * alpha;
+                        g += color.g as u32 * alpha;
+                        b += color.b as u32 * alpha;
+                        a += alpha;
+                        count += 1;
+                    }
+                }
+                // All transparent comes out blank
+                let weight = a.max(1);
+                pixels.push(Color::new((r / weight) as u8, (g / weight) as u8, (b / weight) as u8, (a / count) as u8));
+            }
+        }
+
+        MipLevel { width, height, pixels }
+    }
+
+    // The four texels around a point blended by how close it is to each of their centres
+    fn bilinear(&self, x: f32, y: f32) -> Color {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (left, bottom) = (x.floor(), y.floor());
+        let (fx, fy) = (x - left, y - bottom);
+        let (left, bottom) = (left as i32, bottom as i32);
+
+        let mut sum = [0.0; 4];
+        for (dx, dy, weight) in [(0, 0, (1.0 - fx) * (1.0 - fy)), (1, 0, fx * (1.0 - fy)), (0, 1, (1.0 - fx) * fy), (1, 1, fx * fy)] {
+            let color = self.texel(left + dx, bottom + dy);
+            let weight = weight * color.a as f32;
+            sum[0] += color.r as f32 * weight;
+            sum[1] += color.g as f32 * weight;
+            sum[2] += color.b as f32 * weight;
+            sum[3] += weight;
+        }
+        if sum[3] <= 0.0 {
+            return Color::BLANK;
+        }
+        Color::new((sum[0] / sum[3]) as u8, (sum[1] / sum[3]) as u8, (sum[2] / sum[3]) as u8, sum[3] as u8)
+    }
+}
+
+// A texture at full size and at every halving down to 1x1, made once when textures are loaded
+#[derive(Clone, Debug)]
+pub struct MipChain {
+    pub levels: Vec<MipLevel>,
+}
+
+impl MipChain {
+    pub fn new(image: &Image) -> Self {
+        let mut level = if image.width > 0 && image.height > 0 {
+            MipLevel { width: image.width, height: image.height, pixels: image.get_image_data().to_vec() }
+        } else {
+            MipLevel { width: 1, height: 1, pixels: vec![Color::BLANK] }
+        };
+
+        let mut levels = Vec::new();
+        while level.width > 1 || level.height > 1 {
+            let next = level.halved();
+            levels.push(level);
+            level = next;
+        }
+        levels.push(level);
+
+        MipChain { levels }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.levels[0].height
+    }
+
+    // Full size texel
+    pub fn texel(&self, x: i32, y: i32) -> Color {
+        self.levels[0].texel(x, y)
+    }
+
+    // Colour at a point given in full size texels, where `texels_per_pixel` of them fall on one
+    // screen pixel. Past one texel per pixel a smaller copy is used, whose texels fall about one
+    // to a pixel, so distant surfaces don't skip texels and shimmer as they move.
+    pub fn sample(&self, x: f32, y: f32, texels_per_pixel: f32, filter: TextureFilter) -> Color {
+        let index = match filter {
+            TextureFilter::Nearest => 0,
+            _ => (texels_per_pixel.log2().round().max(0.0) as usize).min(self.levels.len() - 1),
+        };
+        let level = &self.levels[index];
+        let (x, y) = if index == 0 {
+            (x, y)
+        } else {
+            (x * level.width as f32 / self.width() as f32, y * level.height as f32 / self.height() as f32)
+        };
+
+        match filter {
+            TextureFilter::Bilinear => level.bilinear(x, y),
+            _ => level.texel(x.floor() as i32, y.floor() as i32),
+        }
+    }
+}
+
+// Mip chains of every texture, in the same order
+pub fn mip_chains(textures: &[Rc<RefCell<Image>>]) -> Rc<Vec<MipChain>> {
+    Rc::new(textures.iter().map(|texture| MipChain::new(&texture.borrow())).collect())
+}
+
+// Textures the game draws by itself rather than because a level or weapon file names them.
+// They are found by file name, so new textures can go anywhere in the manifest.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BuiltinTextures {
+    pub sky: usize, // Panorama covering a full turn, its bottom row meets the horizon
+    pub grate: usize,
+    pub fence: usize,
+    pub face: usize,
+    pub keys: [usize; 3], // Red, blue and yellow
+    pub bullets: usize,
+    pub shells: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+    pub files: Vec<String>,
+    pub animations: Vec<Animation>,
+    pub builtin: BuiltinTextures,
+}
+
+impl Manifest {
+    // Index of the texture whose file is called `name`, e.g. "sky" for res/sky.png
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.files.iter().position(|file| Path::new(file).file_stem().is_some_and(|stem| stem == name))
+    }
+}
+
+pub fn load_manifest(file_path: &str) -> Result<Manifest, String> {
+    let content = fs::read_to_string(Path::new(file_path))
+        .map_err(|error| format!("Failed to read {}: {}", file_path, error))?;
+
+    let mut manifest = Manifest::default();
+    let mut in_textures = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_textures = line.starts_with("[TEXTURES]");
+            if line.starts_with("[ANIMATION]") {
+                manifest.animations.push(Animation { frame_time: 1, ..Animation::default() });
+            }
+            continue;
+        }
+        if in_textures {
+            manifest.files.push(line.to_string());
+            continue;
+        }
+
+        let (Some(animation), Some((key, value))) = (manifest.animations.last_mut(), line.split_once('=')) else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "texture" => animation.texture = value.parse().unwrap_or(0),
+            "frames" => animation.frames = parse_list(value).unwrap_or_default(),
+            "frame_time" => animation.frame_time = value.parse().unwrap_or(1),
+            _ => {}
+        }
+    }
+
+    // Animations that point at textures that don't exist would index out of the texture list
+    let count = manifest.files.len();
+    manifest.animations.retain(|animation| {
+        let valid = animation.texture < count
+            && !animation.frames.is_empty()
+            && animation.frames.iter().all(|&frame| frame < count)
+            && animation.frame_time > 0;
+        if !valid {
+            eprintln!("texture warning: {}: skipping the animation of texture {}", file_path, animation.texture);
+        }
+        valid
+    });
+
+    let find = |name: &str| manifest.find(name).ok_or(format!("{} has no texture named '{}'", file_path, name));
+    let builtin = BuiltinTextures {
+        sky: find("sky")?,
+        grate: find("grate")?,
+        fence: find("fence")?,
+        face: find("face")?,
+        keys: [find("key_red")?, find("key_blue")?, find("key_yellow")?],
+        bullets: find("bullet")?,
+        shells: find("shells")?,
+    };
+    manifest.builtin = builtin;
+    Ok(manifest)
+}
+
+// Textures are sampled one pixel at a time, which only works for uncompressed images. Any size is
+// fine, coordinates wrap with a modulo. Returns why the image would render wrong, if it would.
+pub fn check_image(image: &Image) -> Option<String> {
+    if image.width <= 0 || image.height <= 0 {
+        return Some(format!("image is {}x{}", image.width, image.height));
+    }
+    if image.format >= PixelFormat::PIXELFORMAT_COMPRESSED_DXT1_RGB as i32 {
+        return Some(format!("compressed pixel format {} can't be sampled, it would render blank", image.format));
+    }
+    None
+}
+
+// The texture each texture index shows at world tick `time`
+pub fn current_frames(frames: &mut Vec<usize>, count: usize, animations: &[Animation], time: usize) {
+    frames.clear();
+    frames.extend(0..count);
+    for animation in animations {
+        frames[animation.texture] = animation.frames[(time / animation.frame_time) % animation.frames.len()];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Texel i of the level is grey level i, so averages are easy to check
+    fn level(width: i32, height: i32) -> MipLevel {
+        let pixels = (0..width * height).map(|i| Color::new(i as u8, i as u8, i as u8, 255)).collect();
+        MipLevel { width, height, pixels }
+    }
+
+    fn grey(color: Color) -> u8 {
+        assert!(color.r == color.g && color.g == color.b && color.a == 255);
+        color.r
+    }
+
+    #[test]
+    fn odd_sizes_halve_over_every_texel() {
+        // 3x3 becomes 1x1, averaging all nine
+        let halved = level(3, 3).halved();
+        assert_eq!((halved.width, halved.height), (1, 1));
+        assert_eq!(grey(halved.texel(0, 0)), 4);
+
+        // 5 columns split into 2 and 3, no column left out or used twice
+        let halved = level(5, 3).halved();
+        assert_eq!((halved.width, halved.height), (2, 1));
+        assert_eq!(grey(halved.texel(0, 0)), [0, 1, 5, 6, 10, 11].iter().sum::<u8>() / 6);
+        assert_eq!(grey(halved.texel(1, 0)), [2, 3, 4, 7, 8, 9, 12, 13, 14].iter().sum::<u8>() / 9);
+    }
+
+    #[test]
+    fn non_square_sizes_halve_down_to_one_texel() {
+        let mut level = level(8, 2);
+        let mut sizes = vec![(level.width, level.height)];
+        while level.width > 1 || level.height > 1 {
+            level = level.halved();
+            sizes.push((level.width, level.height));
+        }
+        assert_eq!(sizes, [(8, 2), (4, 1), (2, 1), (1, 1)]);
+        assert_eq!(grey(level.texel(0, 0)), (0..16).sum::<u8>() / 16);
+    }
+
+    #[test]
+    fn texel_coordinates_wrap() {
+        let level = level(4, 3);
+        assert_eq!(grey(level.texel(-1, -1)), grey(level.texel(3, 2)));
+        assert_eq!(grey(level.texel(-4, -3)), grey(level.texel(0, 0)));
+        assert_eq!(grey(level.texel(-5, 7)), grey(level.texel(3, 1)));
+        assert_eq!(grey(level.texel(9, -7)), grey(level.texel(1, 2)));
+    }
+}
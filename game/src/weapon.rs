@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::Path;
+
+use crate::inventory::AmmoKind;
+use crate::lighting::PointLight;
+use crate::save::parse_list;
+
+#[derive(Clone)]
+pub struct WeaponDef {
+    pub name: String,
+    pub slot: usize,
+    pub frames: Vec<i32>, // Texture indices of the animation, frames[0] is the idle frame
+    pub fire_frame: usize, // Index into `frames` at which the shot goes off
+    pub frame_time: usize, // Frames each animation frame stays on screen
+    pub cooldown: usize, // Frames to wait after the animation before firing again
+    pub ammo: Option<AmmoKind>, // None for melee weapons, "none" in the file
+    pub magazine: i32, // Rounds per reload, 0 feeds straight from the inventory
+    pub reload_time: usize,
+    pub damage: f64,
+    pub spread: f64, // Maximum deviation of a pellet in radians
+    pub pellets: usize,
+    pub hitscan: bool, // Hitscan weapons hit instantly, the others fire projectile sprites
+    pub range: f64,
+    pub automatic: bool, // Keeps firing while the button is held
+    pub flash: Option<PointLight>, // Muzzle flash that lights up the surroundings for a moment
+}
+
+impl Default for WeaponDef {
+    fn default() -> Self {
+        WeaponDef {
+            name: String::new(),
+            slot: 0,
+            frames: Vec::new(),
+            fire_frame: 0,
+            frame_time: 10,
+            cooldown: 0,
+            ammo: None,
+            magazine: 0,
+            reload_time: 0,
+            damage: 0.0,
+            spread: 0.0,
+            pellets: 1,
+            hitscan: true,
+            range: 64.0,
+            automatic: false,
+            flash: None,
+        }
+    }
+}
+
+pub fn load_weapons(file_path: &str) -> Result<Vec<WeaponDef>, String> {
+    let content = fs::read_to_string(Path::new(file_path))
+        .map_err(|error| format!("Failed to read {}: {}", file_path, error))?;
+
+    let mut weapons = Vec::new();
+    let mut current: Option<WeaponDef> = None;
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        // A bad list would shift every value after it, so it stops the load
+        let bad_line = |error: String| format!("{} line {}: {}", file_path, number + 1, error);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with("[WEAPON]") {
+            weapons.extend(current.take());
+            current = Some(WeaponDef::default());
+            continue;
+        }
+
+        let (Some(weapon), Some((key, value))) = (current.as_mut(), line.split_once('=')) else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "name" => weapon.name = value.to_string(),
+            "slot" => weapon.slot = value.parse().unwrap_or(0),
+            "frames" => weapon.frames = parse_list(value).map_err(bad_line)?,
+            "fire_frame" => weapon.fire_frame = value.parse().unwrap_or(0),
+            "frame_time" => weapon.frame_time = value.parse().unwrap_or(10),
+            "cooldown" => weapon.cooldown = value.parse().unwrap_or(0),
+            "ammo" if value == "none" => weapon.ammo = None,
+            "ammo" => weapon.ammo = Some(value.parse().map_err(bad_line)?),
+            "magazine" => weapon.magazine = value.parse().unwrap_or(0),
+            "reload_time" => weapon.reload_time = value.parse().unwrap_or(0),
+            "damage" => weapon.damage = value.parse().unwrap_or(0.0),
+            "spread" => weapon.spread = value.parse().unwrap_or(0.0),
+            "pellets" => weapon.pellets = value.parse().unwrap_or(1),
+            "hitscan" => weapon.hitscan = value == "1" || value == "true",
+            "range" => weapon.range = value.parse().unwrap_or(64.0),
+            "automatic" => weapon.automatic = value == "1" || value == "true",
+            "flash" => weapon.flash = PointLight::from_values(&parse_list(value).map_err(bad_line)?),
+            _ => eprintln!("Unknown weapon property '{}' in {}", key.trim(), file_path),
+        }
+    }
+    weapons.extend(current);
+
+    weapons.retain(|weapon| {
+        let valid = !weapon.frames.is_empty() && weapon.fire_frame < weapon.frames.len();
+        if !valid {
+            eprintln!("Skipping weapon '{}': it needs frames and a fire_frame inside them", weapon.name);
+        }
+        valid
+    });
+    weapons.sort_by_key(|weapon| weapon.slot);
+
+    if weapons.is_empty() {
+        return Err(format!("No usable weapons in {}", file_path));
+    }
+    Ok(weapons)
+}